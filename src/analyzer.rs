@@ -0,0 +1,302 @@
+use crate::{
+    parser::Tree,
+    tokenize::{Loc, Token},
+    types::Type,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Access {
+    // the kernel writes into the buffer
+    Fill,
+    // the kernel reads from the buffer
+    Drain,
+}
+
+struct Signature {
+    number: usize,
+    name: &'static str,
+    buf: usize,
+    len: usize,
+    access: Access,
+}
+
+// indexes are into the syscall args, args[0] being the syscall number
+#[rustfmt::skip]
+static SIGNATURES: [Signature; 9] = [
+    Signature { number: 0, name: "read", buf: 2, len: 3, access: Access::Fill },
+    Signature { number: 1, name: "write", buf: 2, len: 3, access: Access::Drain },
+    Signature { number: 17, name: "pread64", buf: 2, len: 3, access: Access::Fill },
+    Signature { number: 18, name: "pwrite64", buf: 2, len: 3, access: Access::Drain },
+    Signature { number: 44, name: "sendto", buf: 2, len: 3, access: Access::Drain },
+    Signature { number: 45, name: "recvfrom", buf: 2, len: 3, access: Access::Fill },
+    Signature { number: 79, name: "getcwd", buf: 1, len: 2, access: Access::Fill },
+    Signature { number: 89, name: "readlink", buf: 2, len: 3, access: Access::Fill },
+    Signature { number: 318, name: "getrandom", buf: 1, len: 2, access: Access::Fill },
+];
+
+#[derive(Debug, Clone)]
+struct Buffer {
+    name: String,
+    size: Option<usize>,
+    // points into a string literal in .rodata
+    readonly: bool,
+}
+
+// warns about syscalls that read or write past the end of buffers whose size is known
+pub struct Analyzer {
+    file_name: String,
+    buffers: Vec<Buffer>,
+    scopes: Vec<usize>,
+}
+
+impl Analyzer {
+    pub fn new(file_name: &str) -> Self {
+        Self {
+            file_name: file_name.to_string(),
+            buffers: vec![],
+            scopes: vec![],
+        }
+    }
+
+    pub fn analyze(&mut self, trees: &[Tree]) {
+        trees.iter().for_each(|tree| self.analyze_stmt(tree));
+    }
+
+    fn analyze_stmt(&mut self, tree: &Tree) {
+        match tree {
+            Tree::Let(ident, typ, expr)
+            | Tree::Static(ident, typ, expr)
+            | Tree::Const(ident, typ, expr) => {
+                let size = match typ {
+                    Some(Type::Array(..)) => typ.as_ref().map(Type::size),
+                    _ => self.size_of(expr),
+                };
+                // const arrays live in .rodata too
+                let readonly = matches!(tree, Tree::Const(..)) || self.is_readonly(expr);
+                self.buffers.push(Buffer {
+                    name: ident.to_string(),
+                    size,
+                    readonly,
+                });
+            }
+            Tree::Assign(ident, expr) => {
+                let (size, readonly) = (self.size_of(expr), self.is_readonly(expr));
+                if let Some(buffer) = self.buffers.iter_mut().rev().find(|b| b.name == *ident) {
+                    // an array literal is copied into the array the variable already is
+                    if !matches!(**expr, Tree::Array(_)) {
+                        buffer.size = size;
+                    }
+                    buffer.readonly = readonly;
+                }
+            }
+            Tree::If {
+                body, els, els_ifs, ..
+            } => {
+                self.analyze_scope(body);
+                els_ifs.iter().for_each(|stmt| self.analyze_stmt(stmt));
+                self.analyze_scope(els);
+            }
+            Tree::ElsIf { body, .. }
+            | Tree::While { body, .. }
+            | Tree::Loop { body, .. }
+            | Tree::DoWhile { body, .. }
+            | Tree::For { body, .. }
+            | Tree::Defer(body)
+            | Tree::Fn { body, .. } => self.analyze_scope(body),
+            Tree::Match { arms, .. } => arms.iter().for_each(|arm| self.analyze_scope(&arm.body)),
+            Tree::SysCall(args, loc) => self.check_syscall(args, loc),
+            _ => (),
+        }
+    }
+
+    fn analyze_scope(&mut self, body: &[Tree]) {
+        self.scopes.push(self.buffers.len());
+        body.iter().for_each(|stmt| self.analyze_stmt(stmt));
+        self.buffers.truncate(self.scopes.pop().unwrap());
+    }
+
+    // size in bytes of the memory an expression points to, when it's known
+    fn size_of(&self, expr: &Tree) -> Option<usize> {
+        match expr {
+            Tree::String(string) | Tree::Format(_, string) => Some(string.len()),
+            Tree::Array(_) => Self::literal_type(expr).map(|typ| typ.size()),
+            Tree::Ident(ident) => self
+                .buffers
+                .iter()
+                .rev()
+                .find(|b| b.name == *ident)
+                .and_then(|b| b.size),
+            Tree::BinOp(buf, Token::Plus, offset, _) => match (self.size_of(buf), &**offset) {
+                (Some(size), Tree::Number(offset)) => Some(size.saturating_sub(*offset)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // the type the generator gives a literal without an annotation
+    fn literal_type(expr: &Tree) -> Option<Type> {
+        match expr {
+            Tree::Number(_) => Some(Type::I64),
+            Tree::Bool(_) => Some(Type::Bool),
+            Tree::String(_) | Tree::Format(..) => Some(Type::Str),
+            Tree::Array(elems) => {
+                let elem = Self::literal_type(elems.first()?)?;
+                Some(Type::Array(Box::new(elem), elems.len()))
+            }
+            _ => None,
+        }
+    }
+
+    fn is_readonly(&self, expr: &Tree) -> bool {
+        match expr {
+            Tree::String(_) | Tree::Format(..) => true,
+            Tree::Ident(ident) => self
+                .buffers
+                .iter()
+                .rev()
+                .find(|b| b.name == *ident)
+                .is_some_and(|b| b.readonly),
+            Tree::BinOp(buf, Token::Plus, ..) | Tree::Index(buf, ..) => self.is_readonly(buf),
+            _ => false,
+        }
+    }
+
+    fn check_syscall(&self, args: &[Tree], loc: &Loc) {
+        let Some(Tree::Number(number)) = args.first() else {
+            return;
+        };
+        let Some(sig) = SIGNATURES.iter().find(|sig| sig.number == *number) else {
+            return;
+        };
+        let Some(buf) = args.get(sig.buf) else {
+            return;
+        };
+        let what = match buf {
+            Tree::Ident(ident) => format!("`{ident}`"),
+            Tree::String(_) | Tree::Format(..) => "the string literal".to_string(),
+            _ => "the buffer".to_string(),
+        };
+        // the kernel can't write into .rodata, the syscall would fail with EFAULT
+        if sig.access == Access::Fill && self.is_readonly(buf) {
+            panic!(
+                "{} into {} at {}:{}, which is read-only (use a `static` buffer instead)",
+                sig.name, what, self.file_name, loc
+            );
+        }
+        let Some(Tree::Number(len)) = args.get(sig.len) else {
+            return;
+        };
+        let Some(size) = self.size_of(buf) else {
+            return;
+        };
+        if *len <= size {
+            return;
+        }
+        match sig.access {
+            Access::Fill => eprintln!(
+                "warning: {} of {} bytes overflows {} ({} bytes) at {}:{}",
+                sig.name, len, what, size, self.file_name, loc
+            ),
+            Access::Drain => eprintln!(
+                "warning: {} of {} bytes reads past the end of {} ({} bytes) at {}:{}",
+                sig.name, len, what, size, self.file_name, loc
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Context, Parser};
+    use crate::tokenize::Tokenizer;
+
+    fn analyze(input: &str) -> Analyzer {
+        let (tokens, locs) = Tokenizer::new(input.to_string()).tokenize();
+        let trees = Parser::new(&tokens, &locs, Context::default()).parse_tokens();
+        let mut analyzer = Analyzer::new("test.kr");
+        analyzer.analyze(&trees);
+        analyzer
+    }
+
+    fn size(analyzer: &Analyzer, name: &str) -> Option<usize> {
+        analyzer.size_of(&Tree::Ident(name.to_string()))
+    }
+
+    #[test]
+    fn buffers_are_sized_by_their_type_or_value() {
+        let analyzer =
+            analyze("let b: [u8; 16]\nlet s = \"hello\"\nlet n = [1, 2, 3]\nlet x = 5\n");
+        assert_eq!(size(&analyzer, "b"), Some(16));
+        assert_eq!(size(&analyzer, "s"), Some(5));
+        assert_eq!(size(&analyzer, "n"), Some(24));
+        assert_eq!(size(&analyzer, "x"), None);
+    }
+
+    #[test]
+    fn offsets_shrink_what_is_left() {
+        let analyzer = analyze("let b: [u8; 16]\n");
+        let tail = |offset| {
+            Tree::BinOp(
+                Box::new(Tree::Ident("b".to_string())),
+                Token::Plus,
+                Box::new(Tree::Number(offset)),
+                Loc { line: 1, col: 1 },
+            )
+        };
+        assert_eq!(analyzer.size_of(&tail(4)), Some(12));
+        assert_eq!(analyzer.size_of(&tail(20)), Some(0));
+    }
+
+    #[test]
+    fn assignments_resize_and_scopes_forget() {
+        let analyzer =
+            analyze("let s = \"hi\"\ns = \"hello\"\nif (1 == 1) {\n  let t = \"abc\"\n}\n");
+        assert_eq!(size(&analyzer, "s"), Some(5));
+        assert_eq!(size(&analyzer, "t"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "read into the string literal at test.kr:1:1")]
+    fn reading_into_a_literal_is_rejected() {
+        analyze("syscall(0, 0, \"abc\", 2)\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "read into the buffer at test.kr:2:1, which is read-only")]
+    fn reading_into_a_var_holding_a_literal_is_rejected() {
+        analyze("let s = \"abc\"\nsyscall(0, 0, s + 1, 2)\n");
+    }
+
+    #[test]
+    fn statics_are_writable_buffers() {
+        let analyzer =
+            analyze("static buf: [u8; 8]\nsyscall(0, 0, buf, 8)\nsyscall(1, 1, \"abc\", 3)\n");
+        assert_eq!(size(&analyzer, "buf"), Some(8));
+        assert!(!analyzer.is_readonly(&Tree::Ident("buf".to_string())));
+    }
+
+    #[test]
+    #[should_panic(expected = "read into `A` at test.kr:2:1, which is read-only")]
+    fn reading_into_a_const_is_rejected() {
+        analyze("const A: [u8; 2] = [1, 2]\nsyscall(0, 0, A, 2)\n");
+    }
+
+    #[test]
+    fn arrays_are_sized_by_their_element_type() {
+        let analyzer = analyze(
+            "let b: [u8; 4] = [1, 2, 3, 4]\nlet w: [i32; 4]\nlet n = [1, 2, 3]\nlet s = [\"a\", \"b\"]\n",
+        );
+        assert_eq!(size(&analyzer, "b"), Some(4));
+        assert_eq!(size(&analyzer, "w"), Some(16));
+        assert_eq!(size(&analyzer, "n"), Some(24));
+        assert_eq!(size(&analyzer, "s"), Some(32));
+    }
+
+    #[test]
+    fn assigning_an_array_literal_keeps_the_declared_size() {
+        let analyzer = analyze("let b: [u8; 4]\nb = [1, 2, 3, 4]\n");
+        assert_eq!(size(&analyzer, "b"), Some(4));
+    }
+}
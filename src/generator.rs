@@ -1,54 +1,99 @@
-use crate::{parser::Tree, tokenize::Token};
+use std::cell::RefCell;
+
+use crate::{
+    loader::Module,
+    parser::{Arm, Part, Pattern, Spec, Tree},
+    runtime,
+    tokenize::{Loc, Token},
+    types::{Type, Variant},
+};
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub file_name: String,
+    pub bounds_checks: bool,
+    // asserts are checked but not generated
+    pub release: bool,
+    // overflow and division by zero panic instead of wrapping or trapping
+    pub checked_arithmetic: bool,
+    // free poisons blocks and panics on double frees
+    pub debug_alloc: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Storage {
+    Stack(usize),
+    // a label in .data or .bss
+    Global(String),
+    // a label in .rodata
+    ReadOnly(String),
+    // folded into every use
+    Const(Tree),
+}
+
+// where gen_init writes to, a variable or the address held in a temp slot
+enum Dest {
+    Var(Var),
+    Temp(usize),
+}
 
 #[derive(Debug, Clone)]
 struct Var {
     name: String,
-    stack_loc: usize,
+    storage: Storage,
+    typ: Type,
 }
 impl Var {
-    fn new(name: String, stack_loc: usize) -> Self {
-        Self { name, stack_loc }
+    fn new(name: String, storage: Storage, typ: Type) -> Self {
+        Self { name, storage, typ }
     }
 }
 
+// known for every fn before any body is generated, calls can come first
+#[derive(Debug, Clone)]
+struct Function {
+    name: String,
+    params: Vec<Type>,
+    ret: Option<Type>,
+}
+
+// a deferred block and what was in scope when it was deferred
+#[derive(Debug, Clone)]
+struct Deferred {
+    depth: usize,
+    vars: usize,
+    body: Vec<Tree>,
+}
+
 #[derive(Debug, Clone)]
 struct Strs {
     value: String,
     loc: usize,
-    // len: usize,
+    len: usize,
 }
 impl Strs {
     fn new(value: String, loc: usize) -> Self {
-        // not used yet
-        // let len = value.len();
-        Self {
-            value,
-            loc,
-            //len
-        }
+        let len = value.len();
+        Self { value, loc, len }
     }
 
     fn asciz(&self) -> String {
-        // i know i can do it using .into_bytes() but it's boring
+        // runs of letters and digits stay readable, every other byte is written as a number
         let mut str_bytes = String::new();
         let mut buf = String::new();
-        for (i, c) in self.value.chars().enumerate() {
-            match c.is_alphanumeric() {
-                false => {
-                    if !buf.is_empty() {
-                        str_bytes += &format!("\"{buf}\", ");
-                        buf.clear();
-                    }
-                    str_bytes += &(c as u8).to_string();
-                    str_bytes += ", ";
-                }
-                _ => {
-                    buf += &c.to_string();
-                    if i == self.value.len() - 1 {
-                        str_bytes += &format!("\"{buf}\", ");
-                    }
-                }
+        for byte in self.value.bytes() {
+            if byte.is_ascii_alphanumeric() {
+                buf.push(byte as char);
+                continue;
+            }
+            if !buf.is_empty() {
+                str_bytes += &format!("\"{buf}\", ");
+                buf.clear();
             }
+            str_bytes += &format!("{byte}, ");
+        }
+        if !buf.is_empty() {
+            str_bytes += &format!("\"{buf}\", ");
         }
         str_bytes += "0";
         str_bytes
@@ -56,113 +101,297 @@ impl Strs {
 }
 
 static SYSCALL_REGS: [&str; 6] = ["rdi", "rsi", "rdx", "r10", "r8", "r9"];
+// strings and slices take two of them
+static ARG_REGS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+// what asm blocks can bind, rsp holds the frame so it's left out
+static ASM_REGS: [&str; 15] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "r8", "r9", "r10", "r11", "r12", "r13", "r14",
+    "r15",
+];
 
 pub struct Generator {
-    tree: Vec<Tree>,
+    modules: Vec<Module>,
+    options: Options,
     assembly_out: String,
     start_section: String,
     text_section: String,
+    functions_section: String,
     data_section: String,
+    rodata_section: String,
+    bss_section: String,
     vars: Vec<Var>,
+    globals: Vec<Var>,
+    // lets of blocks whose value is being typed before they are generated
+    block_vars: RefCell<Vec<Var>>,
     strs: Vec<Strs>,
     stack: usize,
+    max_stack: usize,
     scopes: Vec<usize>,
+    // module whose names are being generated, unqualified names are looked up in it first
+    module: String,
+    functions: Vec<Function>,
+    // return type and epilogue label of the fn being generated
+    function: Option<(Option<Type>, String)>,
+    defers: Vec<Deferred>,
+    // scope depth of the body of each enclosing loop
+    loops: Vec<usize>,
+    lb_count: usize,
+    runtime: Vec<&'static str>,
+    // args() needs the table of arguments built by _start
+    args: bool,
 }
 
 impl Generator {
-    pub fn new(tree: &Vec<Tree>) -> Self {
+    pub fn new(modules: &[Module], options: Options) -> Self {
         Self {
-            tree: tree.to_vec(),
+            modules: modules.to_vec(),
+            options,
             vars: vec![],
+            globals: vec![],
+            block_vars: RefCell::new(vec![]),
             strs: vec![],
             stack: 0,
+            max_stack: 0,
             scopes: vec![],
+            module: String::new(),
+            functions: vec![],
+            function: None,
+            defers: vec![],
+            loops: vec![],
+            lb_count: 0,
+            runtime: vec![],
+            args: false,
             assembly_out: String::new(),
             start_section: String::new(),
             text_section: String::new(),
+            functions_section: String::new(),
             data_section: String::new(),
+            rodata_section: String::new(),
+            bss_section: String::new(),
         }
     }
 
     pub fn generate_linux_64(&mut self) -> &String {
-        let tree_clone = self.tree.clone();
-        let mut iter = tree_clone.iter().peekable();
+        let modules = self.modules.clone();
         self.data_section += "section .data\n";
+        self.rodata_section += "section .rodata\n";
+        self.bss_section += "section .bss\n";
         self.text_section += "section .text\n\tglobal _start\n";
-        self.start_section += "_start:\n";
+        // the kernel leaves argc, argv and envp at the initial rsp
+        self.start_section += "_start:\n\tmov QWORD [__initial_rsp], rsp\n";
+        self.bss_section += "__initial_rsp: resq 1\n";
         let mut program = String::new();
 
-        while let Some(tree) = iter.peek() {
-            program += &self.gen_linux_64_program(tree);
-            iter.next();
+        modules
+            .iter()
+            .flat_map(|module| &module.trees)
+            .for_each(|tree| {
+                if let Tree::Fn {
+                    name, params, ret, ..
+                } = tree
+                {
+                    self.declare_fn(name, params, ret);
+                }
+            });
+        // with a fn main the main file is only declarations too, _start calls it
+        let main = self.functions.iter().find(|f| f.name == "main").cloned();
+        if let Some(main) = &main {
+            if !main.params.is_empty() {
+                panic!("fn main can't take parameters, args() has the command line");
+            }
+            if let Some(ret) = main.ret.as_ref().filter(|ret| !ret.is_int()) {
+                panic!("fn main returns the exit code, it can't return {:?}", ret);
+            }
+        }
+        // dependencies come first, the main file last
+        for module in &modules {
+            self.module = module.name.clone();
+            self.options.file_name = module.file_name.clone();
+            for tree in &module.trees {
+                let declaration = matches!(
+                    tree,
+                    Tree::Fn { .. } | Tree::Static(..) | Tree::Const(..) | Tree::Empty()
+                );
+                if !module.name.is_empty() && !declaration {
+                    panic!(
+                        "Only declarations can be at the top level of module {}, found {:?}",
+                        module.name, tree
+                    );
+                }
+                if main.is_some() && !declaration {
+                    panic!(
+                        "Only declarations can be at the top level next to fn main, found {:?}",
+                        tree
+                    );
+                }
+                program += &self.gen_linux_64_program(tree);
+            }
+        }
+        // falling off the end is exit(0), or exit(main())
+        match main {
+            Some(main) => {
+                program += "\t;; main() ;;\n";
+                program += &format!("\tcall {}\n", Self::fn_label(&main.name));
+                program += match main.ret {
+                    Some(_) => "\tmov rdi, rax\n",
+                    None => "\txor edi, edi\n",
+                };
+                program += "\tmov rax, 60\n\tsyscall\n";
+            }
+            None => program += &self.gen_linux_64_program(&Tree::Exit(Box::new(Tree::Number(0)))),
         }
 
+        if self.args {
+            self.start_section += runtime::ARGS;
+            self.bss_section += "__args: resq 2\n";
+        }
+        // rsp stays 16 byte aligned, calls push the return address onto an aligned stack
+        self.start_section += "\tand rsp, -16\n";
+        self.start_section += &format!("\tsub rsp, {}\n", self.max_stack.next_multiple_of(2) * 8);
         self.start_section += &program;
 
-        println!("{:?}", self.vars);
-        println!("stack: {}", self.stack);
         self.assembly_out += &self.text_section;
+        self.runtime
+            .iter()
+            .for_each(|routine| self.assembly_out += routine);
+        self.assembly_out += &self.functions_section;
         self.assembly_out += &self.start_section;
         self.assembly_out += &self.data_section;
+        self.assembly_out += &self.rodata_section;
+        self.assembly_out += &self.bss_section;
         &self.assembly_out
     }
     fn gen_linux_64_program(&mut self, tree: &Tree) -> String {
         let mut program = String::new();
         match tree {
-            Tree::Let(ident, expr) => {
+            Tree::Let(ident, typ, expr) => {
                 program += &format!("\t;; Let {} = {:?} ;;\n", ident, expr);
-                if self.vars.iter().any(|var| var.name == ident.to_string()) {
+                if self
+                    .vars
+                    .iter()
+                    .chain(&self.globals)
+                    .any(|var| var.name == *ident)
+                {
                     panic!(
                         "{} is already declared use {} = {:?} instead",
                         ident, ident, expr
                     );
                 }
-                self.vars.push(Var::new(ident.to_string(), self.stack));
-                self.stack += 1;
-                program += &self.handle_vars(&ident, &expr);
+                let typ = match typ {
+                    Some(typ) => {
+                        self.check_types(typ, expr);
+                        typ.clone()
+                    }
+                    None => self.type_of(expr),
+                };
+                let stack_loc = self.alloc_slots(typ.slots());
+                let var = Var::new(ident.to_string(), Storage::Stack(stack_loc), typ);
+                self.vars.push(var);
+                program += &self.handle_vars(ident, expr);
+            }
+
+            Tree::Static(ident, typ, expr) => {
+                let typ = self.declare_global("static", ident, typ, expr);
+                let label = format!("static_{}", Self::mangle(ident));
+                match **expr {
+                    Tree::Empty() => {
+                        self.bss_section += "\talignb 8\n";
+                        self.bss_section += &format!("{}:\n\tresb {}\n", label, typ.size());
+                    }
+                    _ => {
+                        let data = self.gen_data(ident, &typ, expr);
+                        self.data_section += &format!("\talign 8\n{label}:\n{data}");
+                    }
+                }
+                let var = Var::new(ident.to_string(), Storage::Global(label), typ);
+                self.globals.push(var);
+            }
+
+            Tree::Const(ident, typ, expr) => {
+                let typ = self.declare_global("const", ident, typ, expr);
+                let storage = match &typ {
+                    // arrays and structs need an address
+                    _ if typ.is_aggregate() => {
+                        let label = format!("const_{}", Self::mangle(ident));
+                        let data = self.gen_data(ident, &typ, expr);
+                        self.rodata_section += &format!("\talign 8\n{label}:\n{data}");
+                        Storage::ReadOnly(label)
+                    }
+                    _ => match self.fold(expr) {
+                        Some(Tree::Number(value))
+                            if typ.size() < 8 && value >> (typ.size() * 8) != 0 =>
+                        {
+                            panic!("const {} = {} doesn't fit in {:?}", ident, value, typ)
+                        }
+                        Some(value) => Storage::Const(value),
+                        None => panic!(
+                            "const {} must be a compile-time constant, found {:?}",
+                            ident, expr
+                        ),
+                    },
+                };
+                self.globals.push(Var::new(ident.to_string(), storage, typ));
             }
 
             Tree::Assign(ident, expr) => {
                 program += &format!("\t;; {} = {:?} ;;\n", ident, expr);
-                program += &self.handle_vars(&ident, &expr);
+                self.check_mutable(ident);
+                self.check_types(&self.find_var(ident).typ.clone(), expr);
+                program += &self.handle_vars(ident, expr);
+            }
+
+            Tree::Store(target, expr) => {
+                program += &format!("\t;; {:?} = {:?} ;;\n", target, expr);
+                let typ = self.type_of(target);
+                self.check_types(&typ, expr);
+                if let Tree::Index(base, _, loc) = &**target {
+                    if self.is_readonly(base) {
+                        panic!(
+                            "Can't assign into read-only {:?} at {}:{}",
+                            base, self.options.file_name, loc
+                        );
+                    }
+                }
+                match &**target {
+                    _ if typ.is_aggregate() => {
+                        let tmp = self.alloc_temp();
+                        program += &self.gen_addr(target);
+                        program += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+                        program += &self.gen_init(&typ, expr, &Dest::Temp(tmp), 0);
+                        self.free_temp();
+                        return program;
+                    }
+                    Tree::Deref(ptr) if Self::is_simple(ptr) => {
+                        program += &self.gen_value(expr, &typ);
+                        program += &self.gen_expr(ptr, "rbx");
+                    }
+                    _ => {
+                        let tmp = self.alloc_temp();
+                        program += &self.gen_addr(target);
+                        program += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+                        program += &self.gen_value(expr, &typ);
+                        program += &format!("\tmov rbx, QWORD {}\n", self.stack_addr(tmp));
+                        self.free_temp();
+                    }
+                }
+                program += &self.store(&typ, "rbx");
             }
 
             Tree::Inc(var) => {
                 program += &format!("\t;; {}++ ;;\n", var);
-                program += &format!("\tinc QWORD [rsp + {}]\n", self.find_var(var).stack_loc * 8)
+                self.check_mutable(var);
+                program += &self.step_var(var, "inc", "add");
             }
 
             Tree::Dec(var) => {
                 program += &format!("\t;; {}-- ;;\n", var);
-                program += &format!("\tdec QWORD [rsp + {}]\n", self.find_var(var).stack_loc * 8)
+                self.check_mutable(var);
+                program += &self.step_var(var, "dec", "sub");
             }
 
-            Tree::If {
-                expr,
-                body,
-                last_case,
-                next_case,
-                els,
-                els_ifs,
-            } => {
+            Tree::If { expr, .. } => {
                 program += &format!("\t;; If({:?}) ;;\n", expr);
-                program += &self.gen_if_cmp(expr, next_case);
-                program += &self.create_scope(body);
-                if next_case == last_case {
-                    program += &format!(".LB{}:\n", next_case);
-                } else {
-                    program += &format!("\tjmp .LB{}\n", last_case);
-                    els_ifs.iter().for_each(|stmt| {
-                        program += &self.gen_elsif_stmt(stmt, last_case);
-                    });
-                    if !els.is_empty() {
-                        program += "\t;; Els ;;\n";
-                        program += &format!(".LB{}:\n", last_case - 1);
-                        program += &self.create_scope(els);
-                        program += &format!(".LB{}:\n", last_case);
-                        program += "\t;; End Els ;;\n";
-                    }
-                }
+                program += &self.gen_if(tree, None);
                 program += "\t;; End If ;;\n";
             }
 
@@ -175,30 +404,141 @@ impl Generator {
                 program += &format!("\t;; While({:?}) ;;\n", expr);
                 program += &format!("\tjmp .LB{}\n", end);
                 program += &format!(".LB{}:\n", start);
-                program += &self.create_scope(body);
+                program += &self.create_loop_scope(body);
                 program += &format!(".LB{}:\n", end);
                 program += &self.gen_cmp_exp(expr);
                 program += "\ttest al, 1\n";
                 program += &format!("\tjnz .LB{}\n", start);
+                program += &format!(".LB{}:\n", end + 1);
                 program += "\t;; End While ;;\n";
             }
 
-            // Tree::For { var, expr, body } => {}
-            Tree::SysCall(args) => {
-                program += &format!("\t;; Syscall({:?}) ;;\n", args);
-                for i in 1..args.len() {
-                    program += &self.gen_expr(&args[i], SYSCALL_REGS[i - 1]);
+            Tree::Loop { start, body, end } => {
+                program += "\t;; Loop ;;\n";
+                program += &format!(".LB{}:\n", start);
+                program += &self.create_loop_scope(body);
+                program += &format!("\tjmp .LB{}\n", start);
+                program += &format!(".LB{}:\n", end);
+                program += "\t;; End Loop ;;\n";
+            }
+
+            Tree::DoWhile {
+                expr,
+                start,
+                body,
+                end,
+            } => {
+                program += &format!("\t;; DoWhile({:?}) ;;\n", expr);
+                program += &format!(".LB{}:\n", start);
+                program += &self.create_loop_scope(body);
+                program += &self.gen_cmp_exp(expr);
+                program += "\ttest al, 1\n";
+                program += &format!("\tjnz .LB{}\n", start);
+                program += &format!(".LB{}:\n", end);
+                program += "\t;; End DoWhile ;;\n";
+            }
+
+            Tree::Break(end) => {
+                let depth = *self.loops.last().unwrap();
+                program += &self.run_defers(depth, false);
+                program += &format!("\tjmp .LB{}\n", end);
+            }
+
+            Tree::Fn {
+                name,
+                params,
+                ret,
+                body,
+            } => self.gen_fn(name, params, ret, body),
+
+            Tree::Return(expr) => {
+                let Some((ret, end)) = self.function.clone() else {
+                    panic!("return outside of a fn");
+                };
+                program += &format!("\t;; Return({:?}) ;;\n", expr);
+                match (expr, &ret) {
+                    (Some(expr), Some(typ)) => {
+                        self.check_types(typ, expr);
+                        program += &self.gen_value(expr, typ);
+                        program += &self.run_defers_around_value(0, false);
+                    }
+                    (None, None) => program += &self.run_defers(0, false),
+                    (Some(expr), None) => {
+                        panic!("return {:?} from a fn without a return type", expr)
+                    }
+                    (None, Some(typ)) => panic!("Expected a return value of type {:?}", typ),
                 }
-                program += &self.gen_expr(&args[0], "rax");
-                program += "\tsyscall\n";
+                program += &format!("\tjmp {end}\n");
+            }
+
+            Tree::Call(name, args, loc) => program += &self.gen_call(name, args, loc),
+            Tree::Method(base, method, args, loc) => {
+                program += &self.gen_method(base, method, args, loc)
+            }
+
+            Tree::Defer(body) => self.defers.push(Deferred {
+                depth: self.scopes.len(),
+                vars: self.vars.len(),
+                body: body.clone(),
+            }),
+
+            Tree::For {
+                var,
+                expr,
+                start,
+                body,
+                end,
+            } => {
+                program += &format!("\t;; For({} -> {:?}) ;;\n", var, expr);
+                program += &self.gen_for(var, expr, *start, body, *end);
+                program += "\t;; End For ;;\n";
+            }
+
+            Tree::Asm {
+                lines,
+                inputs,
+                clobbers,
+                outputs,
+                loc,
+            } => {
+                program += "\t;; Asm ;;\n";
+                program += &self.gen_asm(lines, inputs, clobbers, outputs, loc);
+                program += "\t;; End Asm ;;\n";
+            }
+
+            Tree::Write(..) | Tree::SysCall(..) => {
+                program += &format!("\t;; {:?} ;;\n", tree);
+                program += &self.gen_expr(tree, "rax");
+            }
+
+            Tree::Match {
+                expr,
+                arms,
+                end,
+                loc,
+            } => {
+                program += &format!("\t;; Match({:?}) ;;\n", expr);
+                program += &self.gen_match(expr, arms, end, loc, None);
+                program += "\t;; End Match ;;\n";
             }
 
             Tree::Exit(expr) => {
                 program += &format!("\t;; Exit({:?}) ;;\n", expr);
-                program += &self.gen_expr(&expr, "rax");
-                program += &self.push("rax");
-                program += "\tmov rax, 60\n";
-                program += &self.pop("rdi");
+                program += &self.gen_expr(expr, "rax");
+                if self.defers.is_empty() {
+                    program += &self.push("rax");
+                    program += "\tmov rax, 60\n";
+                    program += &self.pop("rdi");
+                } else {
+                    // the code is evaluated before the deferred blocks run, in a fn only its own
+                    // defers are known so the callers' are skipped
+                    let tmp = self.alloc_temp();
+                    program += &format!("\tmov QWORD [rsp + {}], rax\n", tmp * 8);
+                    program += &self.run_defers(0, false);
+                    program += &format!("\tmov rdi, QWORD [rsp + {}]\n", tmp * 8);
+                    program += "\tmov rax, 60\n";
+                    self.free_temp();
+                }
                 program += "\tsyscall\n";
                 program += "\t;; End Exit ;;\n";
             }
@@ -207,209 +547,2687 @@ impl Generator {
         program
     }
 
-    fn gen_elsif_stmt(&mut self, stmt: &Tree, last_case: &usize) -> String {
+    // lowered to a while loop over hidden vars, the dot keeps them apart from source names
+    fn gen_for(
+        &mut self,
+        var: &str,
+        expr: &Tree,
+        start: usize,
+        body: &[Tree],
+        end: usize,
+    ) -> String {
         let mut buffer = String::new();
-        match stmt {
-            Tree::ElsIf {
-                curr_case,
-                expr,
-                body,
-                next_case,
-            } => {
-                buffer += &format!("\t;; ElsIf({:?}) ;;\n", expr);
-                buffer += &format!(".LB{}:\n", curr_case);
-                buffer += &self.gen_if_cmp(expr, next_case);
-                self.begin_scope();
-                body.iter().for_each(|stmt| {
-                    buffer += &self.gen_linux_64_program(stmt);
-                });
-                self.end_scope();
-                if next_case != last_case {
-                    buffer += &format!("\tjmp .LB{}\n", last_case);
-                } else {
-                    buffer += &format!(".LB{}:\n", last_case);
-                }
-                buffer += "\t;; End ElsIf ;;\n";
+        let (at, limit, of) = (
+            format!("{var}.at"),
+            format!("{var}.end"),
+            format!("{var}.of"),
+        );
+        let ident = |name: &str| Box::new(Tree::Ident(name.to_string()));
+        let (lets, value) = match expr {
+            Tree::CmpOp(from, Token::DDot, to) => (
+                vec![
+                    Tree::Let(at.clone(), None, from.clone()),
+                    Tree::Let(limit.clone(), None, to.clone()),
+                ],
+                Tree::Ident(at.clone()),
+            ),
+            _ => {
+                // arrays and Vecs are gone over as a slice of them
+                let typ = match self.type_of(expr) {
+                    Type::Str => Type::Str,
+                    typ @ (Type::Array(..) | Type::Slice(_) | Type::Vec(_)) => {
+                        Type::Slice(Box::new(typ.elem().unwrap()))
+                    }
+                    typ => panic!("Can't iterate over {:?} of type {:?}", expr, typ),
+                };
+                // the index never goes out of bounds so the location is never reported
+                let loc = Loc { line: 0, col: 0 };
+                (
+                    vec![
+                        Tree::Let(of.clone(), Some(typ), Box::new(expr.clone())),
+                        Tree::Let(at.clone(), None, Box::new(Tree::Number(0))),
+                        Tree::Let(
+                            limit.clone(),
+                            None,
+                            Box::new(Tree::Field(ident(&of), "len".into())),
+                        ),
+                    ],
+                    Tree::Index(ident(&of), ident(&at), loc),
+                )
             }
-            _ => (),
+        };
+        self.begin_scope();
+        for tree in &lets {
+            buffer += &self.gen_linux_64_program(tree);
         }
+        let mut inner = vec![Tree::Let(var.to_string(), None, Box::new(value))];
+        inner.extend(body.iter().cloned());
+        buffer += &format!("\tjmp .LB{}\n", end);
+        buffer += &format!(".LB{}:\n", start);
+        buffer += &self.create_loop_scope(&inner);
+        buffer += &self.step_var(&at, "inc", "add");
+        buffer += &format!(".LB{}:\n", end);
+        buffer += &self.gen_cmp_exp(&Tree::CmpOp(ident(&at), Token::Less, ident(&limit)));
+        buffer += "\ttest al, 1\n";
+        buffer += &format!("\tjnz .LB{}\n", start);
+        buffer += &format!(".LB{}:\n", end + 1);
+        buffer += &self.end_scope();
         buffer
     }
 
-    fn gen_expr(&mut self, tree: &Tree, reg: &str) -> String {
-        //[TODO] STATIC TYPES & [?]Pointers
-        match tree {
-            Tree::Number(num) => {
-                format!("\tmov {}, {}\n", reg, num)
+    // nothing is kept in registers between statements, so any register but rsp can be clobbered
+    fn gen_asm(
+        &mut self,
+        lines: &[String],
+        inputs: &[(String, Tree)],
+        clobbers: &[String],
+        outputs: &[(String, String)],
+        loc: &Loc,
+    ) -> String {
+        let at = format!("{}:{}", self.options.file_name, loc);
+        let regs = inputs.iter().map(|(reg, _)| reg);
+        for reg in regs
+            .clone()
+            .chain(clobbers)
+            .chain(outputs.iter().map(|(_, reg)| reg))
+        {
+            if !ASM_REGS.contains(&reg.as_str()) {
+                panic!("asm can't use {} at {}", reg, at);
             }
-            Tree::Ident(var) => {
-                format!(
-                    "\tmov {}, QWORD [rsp + {}]\n",
-                    reg,
-                    (self.find_var(var).stack_loc * 8)
-                )
+        }
+        for (i, reg) in regs.clone().enumerate() {
+            if regs.clone().skip(i + 1).any(|other| other == reg) {
+                panic!("{} is bound twice in asm at {}", reg, at);
             }
-            Tree::String(string) => {
-                if let Some(str) = self.strs.iter().find(|str| str.value == *string) {
-                    format!("\tmov {reg}, str_{}\n", str.loc)
-                } else {
-                    let str = Strs::new(string.to_string(), self.strs.len());
-                    self.strs.push(str.clone());
-                    self.data_section += &format!("str_{}:\n\tdb {}\n", str.loc, str.asciz());
-                    format!("\tmov {reg}, str_{}\n", str.loc)
-                }
+        }
+        let mut buffer = String::new();
+        // every input is evaluated before the registers are set, so none of them is overwritten
+        let tmp = self.alloc_slots(inputs.len());
+        for (i, (_, expr)) in inputs.iter().enumerate() {
+            let typ = self.type_of(expr);
+            if typ.is_fat() {
+                panic!(
+                    "pass the ptr and len of {:?} to asm separately at {}",
+                    expr, at
+                );
             }
-            Tree::BinOp(..) => self.gen_bin_exp(tree, reg),
-            Tree::CmpOp(..) => self.gen_cmp_exp(tree),
-            Tree::Empty() => String::new(),
-            _ => {
-                println!("Unexpected expr {:?}", tree);
-                panic!("Unexpected expr")
+            buffer += &self.gen_expr(expr, "rax");
+            buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp + i));
+        }
+        for (i, (reg, _)) in inputs.iter().enumerate() {
+            buffer += &format!("\tmov {reg}, QWORD {}\n", self.stack_addr(tmp + i));
+        }
+        self.stack -= inputs.len();
+        for line in lines {
+            buffer += &format!("\t{line}\n");
+        }
+        let tmp = self.alloc_slots(outputs.len());
+        for (i, (_, reg)) in outputs.iter().enumerate() {
+            buffer += &format!("\tmov QWORD {}, {reg}\n", self.stack_addr(tmp + i));
+        }
+        for (i, (name, _)) in outputs.iter().enumerate() {
+            self.check_mutable(name);
+            let var = self.find_var(name);
+            if var.typ.is_fat() || var.typ.is_aggregate() {
+                panic!("asm can't write {} of type {:?} at {}", name, var.typ, at);
             }
+            let store = self.store(&var.typ, &self.var_ref(var, 0));
+            buffer += &format!("\tmov rax, QWORD {}\n", self.stack_addr(tmp + i));
+            buffer += &store;
         }
+        self.stack -= outputs.len();
+        buffer
     }
 
-    fn gen_bin_op(&mut self, left: &Tree, right: &Tree, op: &str, reg: &str) -> String {
+    // with `value`, every branch leaves its last expression in rax (and rdx)
+    fn gen_if(&mut self, tree: &Tree, value: Option<&Type>) -> String {
         let mut buffer = String::new();
-        buffer += &format!("\t;; BinOp({:?} {op} {:?}) ;;\n", left, right);
-        let mut lreg = "rax";
-        let mut rreg = "rbx";
-        buffer += &self.gen_expr(left, "rax");
-        match left {
-            Tree::BinOp(..) | Tree::CmpOp(..) => {
-                buffer += "\tmov rcx, rax\n";
-                lreg = "rcx";
-                match right {
-                    Tree::BinOp(..) | Tree::CmpOp(..) => {
-                        rreg = "rax";
-                    }
-                    _ => (),
-                }
+        let Tree::If {
+            expr,
+            body,
+            last_case,
+            next_case,
+            els,
+            els_ifs,
+        } = tree
+        else {
+            panic!("Expected If Tree");
+        };
+        buffer += &self.gen_if_cmp(expr, next_case);
+        buffer += &self.gen_body(body, value);
+        if next_case == last_case {
+            buffer += &format!(".LB{}:\n", next_case);
+        } else {
+            buffer += &format!("\tjmp .LB{}\n", last_case);
+            els_ifs.iter().for_each(|stmt| {
+                buffer += &self.gen_elsif_stmt(stmt, last_case, value);
+            });
+            if !els.is_empty() {
+                buffer += "\t;; Els ;;\n";
+                buffer += &format!(".LB{}:\n", last_case - 1);
+                buffer += &self.gen_body(els, value);
+                buffer += &format!(".LB{}:\n", last_case);
+                buffer += "\t;; End Els ;;\n";
             }
-            _ => match right {
-                Tree::BinOp(..) | Tree::CmpOp(..) => {
-                    buffer += "\tmov rcx, rax\n";
-                    lreg = "rcx";
-                }
-                _ => (),
-            },
         }
-        buffer += &self.gen_expr(right, "rbx");
-        match op {
-            "div" => {
-                if lreg != "rax" {
-                    lreg = "rax";
-                    buffer += &format!("\tmov rax, {lreg}\n");
-                }
-                if rreg != "rbx" {
-                    rreg = "rbx";
-                    buffer += &format!("\tmov rbx, {rreg}\n");
+        buffer
+    }
+
+    fn gen_body(&mut self, body: &[Tree], value: Option<&Type>) -> String {
+        match value {
+            Some(typ) => self.gen_block_value(body, typ),
+            None => self.create_scope(body),
+        }
+    }
+
+    // runs a block and evaluates its last expression into rax (and rdx)
+    fn gen_block_value(&mut self, body: &[Tree], typ: &Type) -> String {
+        let mut buffer = String::new();
+        self.begin_scope();
+        if let Some((last, stmts)) = body.split_last() {
+            stmts.iter().for_each(|stmt| {
+                buffer += &self.gen_linux_64_program(stmt);
+            });
+            match last {
+                // the branch never produces a value
+                _ if self.diverges(last) => buffer += &self.gen_linux_64_program(last),
+                _ => {
+                    self.check_types(typ, last);
+                    buffer += &self.gen_value(last, typ);
+                    buffer += &self.run_defers_around_value(self.scopes.len(), true);
                 }
-                buffer += "\txor rdx, rdx\n";
-                buffer += &format!("\t{} {rreg}\n", op);
             }
-            _ => buffer += &format!("\t{} {lreg}, {rreg}\n", op),
-        }
-        if lreg != reg {
-            buffer += &format!("\tmov {reg}, {lreg}\n");
+        } else {
+            panic!("Expected a value of type {:?}, found an empty block", typ);
         }
-        buffer += &format!("\t;; End BinOp({:?} {op} {:?}) ;;\n", left, right);
+        buffer += &self.end_scope();
         buffer
     }
 
-    fn gen_bin_exp(&mut self, tree: &Tree, lreg: &str) -> String {
-        match tree {
-            Tree::BinOp(left, op, right) => match op {
-                Token::Plus => self.gen_bin_op(left, right, "add", lreg),
-                Token::Minus => self.gen_bin_op(left, right, "sub", lreg),
-                Token::Multiply => self.gen_bin_op(left, right, "imul", lreg),
-                Token::Divide => self.gen_bin_op(left, right, "div", lreg),
-                _ => panic!("invalid Token"),
-            },
-            _ => panic!("Expected BinOp Tree"),
+    // if and match used as values
+    fn gen_block_expr(&mut self, tree: &Tree, reg: &str) -> String {
+        let typ = self.type_of(tree);
+        if typ.is_aggregate() {
+            panic!("{:?} values can't be produced by blocks yet", typ);
+        }
+        let mut buffer = match tree {
+            Tree::If { els, .. } if els.is_empty() => {
+                panic!("if used as a value needs an els branch")
+            }
+            Tree::If { .. } => self.gen_if(tree, Some(&typ)),
+            Tree::Match {
+                expr,
+                arms,
+                end,
+                loc,
+            } => self.gen_match(expr, arms, end, loc, Some(&typ)),
+            _ => panic!("Expected If or Match Tree"),
+        };
+        if reg != "rax" {
+            buffer += &format!("\tmov {reg}, rax\n");
         }
+        buffer
     }
 
-    fn gen_cmp_op(&mut self, left: &Tree, op: &str, right: &Tree) -> String {
+    fn gen_elsif_stmt(&mut self, stmt: &Tree, last_case: &usize, value: Option<&Type>) -> String {
         let mut buffer = String::new();
-        buffer += &self.gen_expr(left, "rax");
-        match right {
-            Tree::BinOp(..) | Tree::CmpOp(..) => {
-                buffer += "\tmov rcx, rax\n";
-                buffer += &self.gen_expr(right, "rbx");
-                buffer += "\tmov rax, rcx\n";
+        if let Tree::ElsIf {
+            curr_case,
+            expr,
+            body,
+            next_case,
+        } = stmt
+        {
+            buffer += &format!("\t;; ElsIf({:?}) ;;\n", expr);
+            buffer += &format!(".LB{}:\n", curr_case);
+            buffer += &self.gen_if_cmp(expr, next_case);
+            buffer += &self.gen_body(body, value);
+            if next_case != last_case {
+                buffer += &format!("\tjmp .LB{}\n", last_case);
+            } else {
+                buffer += &format!(".LB{}:\n", last_case);
             }
-            _ => buffer += &self.gen_expr(right, "rbx"),
+            buffer += "\t;; End ElsIf ;;\n";
         }
-        buffer += "\tcmp rax, rbx\n";
-        buffer += &format!("\t{} al\n", op);
-        buffer += &format!("\tmovzx rax, al\n");
         buffer
     }
 
-    fn gen_cmp_exp(&mut self, tree: &Tree) -> String {
-        //[TODO] Adding the rest of CmpOp
+    fn gen_expr(&mut self, tree: &Tree, reg: &str) -> String {
+        //[TODO] STATIC TYPES
         match tree {
-            Tree::CmpOp(left, op, right) => {
-                let op_str = match op {
-                    Token::EquEqu => "sete",
-                    Token::NotEqu => "setne",
-                    Token::Greater => "setg",
-                    Token::GreatEqu => "setge",
-                    Token::Less => "setl",
-                    Token::LessEqu => "setle",
-                    _ => "",
+            Tree::Number(num) => {
+                format!("\tmov {}, {}\n", reg, num)
+            }
+            Tree::Bool(value) => format!("\tmov {}, {}\n", reg, *value as usize),
+            Tree::Index(..) | Tree::Deref(_) | Tree::Field(..)
+                if self.is_place(tree) && self.type_of(tree).is_fat() =>
+            {
+                let mut buffer = self.gen_fat(tree);
+                if reg != "rax" {
+                    buffer += &format!("\tmov {reg}, rax\n");
+                }
+                buffer
+            }
+            Tree::Ident(var) if self.is_const(var) => {
+                let value = self.const_value(var);
+                self.gen_expr(&value, reg)
+            }
+            // simple operands must only touch `reg`
+            Tree::Ident(var) if self.type_of(tree).is_aggregate() => {
+                format!("\tlea {}, {}\n", reg, self.var_addr(self.find_var(var)))
+            }
+            Tree::Index(..) | Tree::Deref(_) | Tree::Field(..)
+                if self.is_place(tree) && self.type_of(tree).is_aggregate() =>
+            {
+                let mut buffer = self.gen_addr(tree);
+                if reg != "rax" {
+                    buffer += &format!("\tmov {reg}, rax\n");
+                }
+                buffer
+            }
+            Tree::Ident(var) => {
+                let var = self.find_var(var);
+                self.load(&var.typ, reg, &self.var_ref(var, 0))
+            }
+            Tree::String(string) | Tree::Format(_, string) => {
+                format!("\tmov {reg}, str_{}\n", self.intern(string).loc)
+            }
+            Tree::Null => format!("\tmov {}, 0\n", reg),
+            Tree::AddrOf(expr) => {
+                let mut buffer = self.gen_addr(expr);
+                if reg != "rax" {
+                    buffer += &format!("\tmov {reg}, rax\n");
+                }
+                buffer
+            }
+            Tree::Deref(_) | Tree::Index(..) => {
+                let typ = self.type_of(tree);
+                let mut buffer = self.gen_addr(tree);
+                buffer += &self.load(&typ, reg, "rax");
+                buffer
+            }
+            Tree::Field(base, field) => self.gen_field(base, field, reg),
+            Tree::Write(fd, string) => {
+                let mut buffer = String::new();
+                let tmp = self.alloc_temp();
+                if !Self::is_simple(fd) {
+                    buffer += &self.gen_expr(fd, "rax");
+                    buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+                }
+                buffer += &self.gen_fat(string);
+                buffer += "\tmov rsi, rax\n";
+                match Self::is_simple(fd) {
+                    true => buffer += &self.gen_expr(fd, "rdi"),
+                    false => buffer += &format!("\tmov rdi, QWORD {}\n", self.stack_addr(tmp)),
+                }
+                self.free_temp();
+                buffer += "\tmov rax, 1\n";
+                buffer += "\tsyscall\n";
+                if reg != "rax" {
+                    buffer += &format!("\tmov {reg}, rax\n");
+                }
+                buffer
+            }
+            Tree::If { .. } | Tree::Match { .. } => self.gen_block_expr(tree, reg),
+            Tree::SysCall(args, _) => {
+                let mut buffer = self.gen_syscall(args);
+                if reg != "rax" {
+                    buffer += &format!("\tmov {reg}, rax\n");
+                }
+                buffer
+            }
+            Tree::Call(name, args, loc) => {
+                let mut buffer = self.gen_call(name, args, loc);
+                if reg != "rax" {
+                    buffer += &format!("\tmov {reg}, rax\n");
+                }
+                buffer
+            }
+            Tree::Method(base, method, args, loc) => {
+                let mut buffer = self.gen_method(base, method, args, loc);
+                if reg != "rax" {
+                    buffer += &format!("\tmov {reg}, rax\n");
+                }
+                buffer
+            }
+            Tree::Array(_) => panic!("Array literals can only initialize arrays"),
+            Tree::StructLit(..) => panic!("Struct literals can only initialize structs"),
+            Tree::Variant(typ, name, args) if !typ.is_aggregate() => {
+                let tag = self.enum_variant(typ, name, args).tag;
+                format!("\tmov {reg}, {tag}\n")
+            }
+            Tree::Variant(..) => panic!("Variants with a payload can only initialize enums"),
+            Tree::BinOp(..) => self.gen_bin_exp(tree, reg),
+            Tree::CmpOp(..) => {
+                let mut buffer = self.gen_cmp_exp(tree);
+                if reg != "rax" {
+                    buffer += &format!("\tmov {reg}, rax\n");
+                }
+                buffer
+            }
+            Tree::Empty() => String::new(),
+            _ => panic!("Unexpected expr {:?}", tree),
+        }
+    }
+
+    // leaves the address of an lvalue in rax
+    fn gen_addr(&mut self, tree: &Tree) -> String {
+        match tree {
+            Tree::Ident(var) => format!("\tlea rax, {}\n", self.var_addr(self.find_var(var))),
+            Tree::Deref(expr) => self.gen_expr(expr, "rax"),
+            Tree::Index(base, index, loc) => match self.type_of(base) {
+                Type::Array(elem, len) => self.gen_array_index(base, index, &elem, len, loc),
+                typ @ (Type::Str | Type::Slice(_) | Type::Vec(_)) => {
+                    self.gen_slice_index(base, index, &typ.elem().unwrap(), loc)
+                }
+                // base decays to a pointer, so this is plain pointer arithmetic
+                Type::Ptr(_) => {
+                    let elem = Tree::BinOp(base.clone(), Token::Plus, index.clone(), *loc);
+                    self.gen_expr(&elem, "rax")
+                }
+                typ => panic!("Can't index into {:?} of type {:?}", base, typ),
+            },
+            Tree::Field(base, field) => {
+                let typ = self.type_of(base);
+                let (offset, _) = self.struct_field(&typ, field);
+                let mut buffer = match typ {
+                    Type::Ptr(_) => self.gen_expr(base, "rax"),
+                    _ => self.gen_addr(base),
                 };
-                self.gen_cmp_op(&left, op_str, &right)
+                if offset != 0 {
+                    buffer += &format!("\tadd rax, {offset}\n");
+                }
+                buffer
             }
-            _ => panic!("Expected CMP OP"),
+            _ => panic!("Can't take the address of {:?}", tree),
         }
     }
 
-    fn gen_if_cmp(&mut self, expr: &Tree, next_case: &usize) -> String {
+    fn gen_array_index(
+        &mut self,
+        base: &Tree,
+        index: &Tree,
+        elem: &Type,
+        len: usize,
+        loc: &Loc,
+    ) -> String {
         let mut buffer = String::new();
-        buffer += &self.gen_cmp_exp(&expr);
-        buffer += "\ttest al, 1\n";
-        buffer += &format!("\tjz .LB{}\n", next_case);
+        // constant indexes are checked here instead of at runtime
+        if let Tree::Number(i) = index {
+            if *i >= len {
+                panic!(
+                    "index {} out of bounds for array of length {} at {}:{}",
+                    i, len, self.options.file_name, loc
+                );
+            }
+            buffer += &self.gen_addr(base);
+            if *i > 0 {
+                buffer += &format!("\tadd rax, {}\n", i * elem.size());
+            }
+            return buffer;
+        }
+        buffer += &self.gen_expr(index, "rax");
+        if self.options.bounds_checks {
+            buffer += &self.gen_bounds_check(len, loc);
+        }
+        match base {
+            Tree::Ident(var) => {
+                buffer += &format!("\tlea rbx, {}\n", self.var_addr(self.find_var(var)));
+            }
+            _ => {
+                let tmp = self.alloc_temp();
+                buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+                buffer += &self.gen_addr(base);
+                buffer += "\tmov rbx, rax\n";
+                buffer += &format!("\tmov rax, QWORD {}\n", self.stack_addr(tmp));
+                self.free_temp();
+            }
+        }
+        if elem.size() != 1 {
+            buffer += &format!("\timul rax, rax, {}\n", elem.size());
+        }
+        buffer += "\tadd rax, rbx\n";
         buffer
     }
 
-    fn handle_vars(&mut self, ident: &String, expr: &Tree) -> String {
+    fn gen_slice_index(&mut self, base: &Tree, index: &Tree, elem: &Type, loc: &Loc) -> String {
         let mut buffer = String::new();
-        let stack_loc = (self.find_var(ident).stack_loc * 8).to_string();
-        buffer += &self.gen_expr(expr, &format!("QWORD [rsp + {}]", stack_loc));
+        let tmp = self.alloc_temp();
+        buffer += &self.gen_expr(index, "rax");
+        buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+        buffer += &self.gen_fat(base);
+        buffer += "\tmov rbx, rax\n";
+        buffer += &format!("\tmov rax, QWORD {}\n", self.stack_addr(tmp));
+        self.free_temp();
+        if self.options.bounds_checks {
+            buffer += "\tcmp rax, rdx\n";
+            buffer += &self.gen_bounds_fail("jb", loc);
+        }
+        if elem.size() != 1 {
+            buffer += &format!("\timul rax, rax, {}\n", elem.size());
+        }
+        buffer += "\tadd rax, rbx\n";
         buffer
     }
 
-    fn begin_scope(&mut self) {
-        self.scopes.push(self.vars.len())
-    }
-    fn end_scope(&mut self) {
-        let pop_count = self.vars.len() - self.scopes.last().unwrap();
-        // not used for now
-        // let mut buffer = String::new();
-        // buffer += &format!("\tadd rsp, {}\n", pop_count * 8);
-        self.stack -= pop_count;
-        for _ in 0..pop_count {
-            self.vars.pop();
+    // `base[start..end]` into rax (pointer) and rdx (length)
+    fn gen_slice(&mut self, base: &Tree, start: &Tree, end: &Tree, loc: &Loc) -> String {
+        let size = match self.type_of(base) {
+            typ @ (Type::Array(..) | Type::Str | Type::Slice(_) | Type::Vec(_)) => {
+                typ.elem().unwrap().size()
+            }
+            typ => panic!("Can't slice {:?} of type {:?}", base, typ),
+        };
+        // constant ranges over arrays are checked here instead of at runtime
+        let checked = match (self.type_of(base), start, end) {
+            (Type::Array(_, len), Tree::Number(start), Tree::Number(end)) => {
+                if start > end || *end > len {
+                    panic!(
+                        "range {}..{} out of bounds for array of length {} at {}:{}",
+                        start, end, len, self.options.file_name, loc
+                    );
+                }
+                true
+            }
+            _ => !self.options.bounds_checks,
+        };
+        let mut buffer = String::new();
+        let (ptr, len, from) = (self.alloc_temp(), self.alloc_temp(), self.alloc_temp());
+        buffer += &self.gen_fat(base);
+        buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(ptr));
+        buffer += &format!("\tmov QWORD {}, rdx\n", self.stack_addr(len));
+        buffer += &self.gen_expr(start, "rax");
+        buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(from));
+        match end {
+            Tree::Empty() => buffer += &format!("\tmov rax, QWORD {}\n", self.stack_addr(len)),
+            _ => buffer += &self.gen_expr(end, "rax"),
+        }
+        if !checked {
+            buffer += &format!("\tcmp rax, QWORD {}\n", self.stack_addr(len));
+            buffer += &self.gen_bounds_fail("jbe", loc);
+            buffer += &format!("\tcmp QWORD {}, rax\n", self.stack_addr(from));
+            buffer += &self.gen_bounds_fail("jbe", loc);
+        }
+        buffer += "\tmov rdx, rax\n";
+        buffer += &format!("\tmov rax, QWORD {}\n", self.stack_addr(from));
+        buffer += "\tsub rdx, rax\n";
+        if size != 1 {
+            buffer += &format!("\timul rax, rax, {size}\n");
+        }
+        buffer += &format!("\tadd rax, QWORD {}\n", self.stack_addr(ptr));
+        self.stack -= 3;
+        buffer
+    }
+
+    fn intern(&mut self, string: &str) -> Strs {
+        if let Some(str) = self.strs.iter().find(|str| str.value == *string) {
+            return str.clone();
+        }
+        let str = Strs::new(string.to_string(), self.strs.len());
+        self.strs.push(str.clone());
+        self.rodata_section += &format!("str_{}:\n\tdb {}\n", str.loc, str.asciz());
+        str
+    }
+
+    // strings and slices go into rax (pointer) and rdx (length)
+    fn gen_fat(&mut self, tree: &Tree) -> String {
+        match tree {
+            Tree::String(string) | Tree::Format(_, string) => {
+                let str = self.intern(string);
+                format!("\tmov rax, str_{}\n\tmov rdx, {}\n", str.loc, str.len)
+            }
+            Tree::Index(base, index, loc) => match &**index {
+                Tree::CmpOp(start, Token::DDot, end) => self.gen_slice(base, start, end, loc),
+                _ => self.gen_fat_load(tree),
+            },
+            Tree::Empty() => "\txor eax, eax\n\txor edx, edx\n".to_string(),
+            Tree::BinOp(left, Token::Plus, right, loc) if self.is_concat(left, right) => {
+                self.gen_concat(left, right, loc)
+            }
+            Tree::If { .. } | Tree::Match { .. } => self.gen_block_expr(tree, "rax"),
+            Tree::Call(name, args, loc) => self.gen_call(name, args, loc),
+            Tree::Method(base, method, args, loc) => self.gen_method(base, method, args, loc),
+            Tree::Ident(var) if self.is_const(var) => {
+                let value = self.const_value(var);
+                self.gen_fat(&value)
+            }
+            _ => self.gen_fat_load(tree),
+        }
+    }
+
+    // `+` on a str and another str, []u8 or String
+    fn is_concat(&self, left: &Tree, right: &Tree) -> bool {
+        let (ltyp, rtyp) = (self.type_of(left), self.type_of(right));
+        ltyp.is_text() && rtyp.is_text() && (ltyp == Type::Str || rtyp == Type::Str)
+    }
+
+    // copies both sides into a new heap block on every `+`, nothing frees it but `free(s.ptr)`,
+    // so text built up in a loop belongs in a String that appends in place
+    fn gen_concat(&mut self, left: &Tree, right: &Tree, loc: &Loc) -> String {
+        self.use_heap();
+        let tmp = self.alloc_slots(4);
+        let mut buffer = String::new();
+        for (i, side) in [left, right].into_iter().enumerate() {
+            buffer += &self.gen_fat(side);
+            buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp + i * 2));
+            buffer += &format!("\tmov QWORD {}, rdx\n", self.stack_addr(tmp + i * 2 + 1));
+        }
+        buffer += &format!("\tmov rdi, QWORD {}\n", self.stack_addr(tmp + 1));
+        buffer += &format!("\tadd rdi, QWORD {}\n", self.stack_addr(tmp + 3));
+        buffer += "\tcall __alloc\n";
+        buffer += "\ttest rax, rax\n";
+        let reason = Tree::String("allocation failed".to_string());
+        buffer += &self.gen_check("jnz", &reason, loc);
+        buffer += "\tmov rdi, rax\n";
+        for i in 0..2 {
+            buffer += &format!("\tmov rsi, QWORD {}\n", self.stack_addr(tmp + i * 2));
+            buffer += &format!("\tmov rcx, QWORD {}\n", self.stack_addr(tmp + i * 2 + 1));
+            buffer += "\trep movsb\n";
+        }
+        buffer += &format!("\tmov rdx, QWORD {}\n", self.stack_addr(tmp + 1));
+        buffer += &format!("\tadd rdx, QWORD {}\n", self.stack_addr(tmp + 3));
+        buffer += "\tsub rdi, rdx\n";
+        buffer += "\tmov rax, rdi\n";
+        self.stack -= 4;
+        buffer
+    }
+
+    fn gen_fat_load(&mut self, tree: &Tree) -> String {
+        match self.type_of(tree) {
+            Type::Array(_, len) => {
+                let mut buffer = self.gen_expr(tree, "rax");
+                buffer += &format!("\tmov rdx, {len}\n");
+                buffer
+            }
+            // a Vec is used as a slice of its elements
+            Type::Vec(_) => {
+                let mut buffer = self.gen_expr(tree, "rax");
+                buffer += "\tmov rdx, QWORD [rax + 8]\n";
+                buffer += "\tmov rax, QWORD [rax]\n";
+                buffer
+            }
+            typ if typ.is_fat() => match tree {
+                Tree::Ident(var) => {
+                    let var = self.find_var(var);
+                    format!(
+                        "\tmov rax, QWORD {}\n\tmov rdx, QWORD {}\n",
+                        self.var_addr(var),
+                        self.var_addr_off(var, 8)
+                    )
+                }
+                _ => {
+                    let mut buffer = self.gen_addr(tree);
+                    buffer += "\tmov rdx, QWORD [rax + 8]\n";
+                    buffer += "\tmov rax, QWORD [rax]\n";
+                    buffer
+                }
+            },
+            typ => panic!(
+                "Expected a string or slice, found {:?} of type {:?}",
+                tree, typ
+            ),
+        }
+    }
+
+    fn gen_field(&mut self, base: &Tree, field: &str, reg: &str) -> String {
+        if Self::vec_elem(&self.type_of(base)).is_some() {
+            let offset = match field {
+                "ptr" => 0,
+                "len" => 8,
+                _ => panic!("{:?} is a Vec, it has no field {}", base, field),
+            };
+            // a Vec and a pointer to one both evaluate to its address
+            let mut buffer = self.gen_expr(base, reg);
+            buffer += &format!("\tmov {reg}, QWORD [{reg} + {offset}]\n");
+            return buffer;
+        }
+        if let Some(typ) = self.type_of(base).as_struct() {
+            let (offset, field_typ) = self.struct_field(typ, field);
+            // a field of a variable only needs `reg`, which keeps it a simple operand
+            return match (base, self.type_of(base)) {
+                (Tree::Ident(var), Type::Struct { .. }) => {
+                    let var = self.find_var(var);
+                    match field_typ.is_aggregate() {
+                        true => format!("\tlea {reg}, {}\n", self.var_addr_off(var, offset)),
+                        false => self.load(&field_typ, reg, &self.var_ref(var, offset)),
+                    }
+                }
+                (Tree::Ident(var), _) => {
+                    let var = self.find_var(var);
+                    let mut buffer = self.load(&var.typ, reg, &self.var_ref(var, 0));
+                    buffer += &match field_typ.is_aggregate() {
+                        true => format!("\tlea {reg}, [{reg} + {offset}]\n"),
+                        false => self.load(&field_typ, reg, &format!("{reg} + {offset}")),
+                    };
+                    buffer
+                }
+                _ => {
+                    let mut buffer =
+                        self.gen_addr(&Tree::Field(Box::new(base.clone()), field.to_string()));
+                    match field_typ.is_aggregate() {
+                        true if reg != "rax" => buffer += &format!("\tmov {reg}, rax\n"),
+                        true => (),
+                        false => buffer += &self.load(&field_typ, reg, "rax"),
+                    }
+                    buffer
+                }
+            };
+        }
+        let (typ, offset) = match (self.type_of(base), field) {
+            (Type::Array(_, len), "len") => return format!("\tmov {reg}, {len}\n"),
+            (typ, "ptr") if typ.is_fat() => (typ, 0),
+            (typ, "len") if typ.is_fat() => (typ, 8),
+            (typ, _) => panic!("{:?} of type {:?} has no field {}", base, typ, field),
+        };
+        match base {
+            Tree::String(string) | Tree::Format(_, string) => {
+                let str = self.intern(string);
+                match offset {
+                    0 => format!("\tmov {reg}, str_{}\n", str.loc),
+                    _ => format!("\tmov {reg}, {}\n", str.len),
+                }
+            }
+            Tree::Ident(var) if self.is_const(var) => {
+                let value = self.const_value(var);
+                self.gen_field(&value, field, reg)
+            }
+            Tree::Ident(var) if typ.is_fat() => {
+                let var = self.find_var(var);
+                format!("\tmov {reg}, QWORD {}\n", self.var_addr_off(var, offset))
+            }
+            _ => {
+                let mut buffer = self.gen_fat(base);
+                let src = if offset == 0 { "rax" } else { "rdx" };
+                if reg != src {
+                    buffer += &format!("\tmov {reg}, {src}\n");
+                }
+                buffer
+            }
+        }
+    }
+
+    // the result is left in rax
+    fn gen_syscall(&mut self, args: &[Tree]) -> String {
+        let mut buffer = String::new();
+        buffer += &format!("\t;; Syscall({:?}) ;;\n", args);
+        if args.iter().all(Self::is_simple) {
+            for i in 1..args.len() {
+                buffer += &self.gen_expr(&args[i], SYSCALL_REGS[i - 1]);
+            }
+            buffer += &self.gen_expr(&args[0], "rax");
+        } else {
+            // evaluating an argument may clobber registers already loaded
+            // (div uses rdx), so everything goes through temps first
+            let base = self.stack;
+            for arg in args {
+                let tmp = self.alloc_temp();
+                buffer += &self.gen_expr(arg, "rax");
+                buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+            }
+            for i in 1..args.len() {
+                buffer += &format!(
+                    "\tmov {}, QWORD {}\n",
+                    SYSCALL_REGS[i - 1],
+                    self.stack_addr(base + i)
+                );
+            }
+            buffer += &format!("\tmov rax, QWORD {}\n", self.stack_addr(base));
+            self.stack = base;
+        }
+        buffer += "\tsyscall\n";
+        buffer
+    }
+
+    // evaluates expr into rax, or rax and rdx for strings and slices
+    fn gen_value(&mut self, expr: &Tree, typ: &Type) -> String {
+        match typ.is_fat() {
+            true => self.gen_fat(expr),
+            false => self.gen_expr(expr, "rax"),
+        }
+    }
+
+    // checks the index in rax against len
+    fn gen_bounds_check(&mut self, len: usize, loc: &Loc) -> String {
+        let mut buffer = format!("\tcmp rax, {len}\n");
+        buffer += &self.gen_bounds_fail("jb", loc);
+        buffer
+    }
+
+    // jumps over the failure path with `jump` after a cmp
+    fn gen_bounds_fail(&mut self, jump: &str, loc: &Loc) -> String {
+        let mut buffer = String::new();
+        let ok = self.create_lb();
+        let msg = format!(
+            "index out of bounds at {}:{}\n",
+            self.options.file_name, loc
+        );
+        self.use_runtime(runtime::BOUNDS_FAIL);
+        buffer += &format!("\t{jump} {ok}\n");
+        buffer += &format!("\tmov rdx, {}\n", msg.len());
+        buffer += &self.gen_expr(&Tree::String(msg), "rsi");
+        buffer += "\tjmp __bounds_fail\n";
+        buffer += &format!("{ok}:\n");
+        buffer
+    }
+
+    // evaluates left into rax and right into rbx
+    fn gen_operands(&mut self, left: &Tree, right: &Tree) -> String {
+        let mut buffer = self.gen_expr(left, "rax");
+        if Self::is_simple(right) {
+            buffer += &self.gen_expr(right, "rbx");
+        } else {
+            let tmp = self.alloc_temp();
+            buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+            buffer += &self.gen_expr(right, "rbx");
+            buffer += &format!("\tmov rax, QWORD {}\n", self.stack_addr(tmp));
+            self.free_temp();
+        }
+        buffer
+    }
+
+    // with `check`, overflow and division by zero panic at that loc instead of wrapping
+    fn gen_bin_op(
+        &mut self,
+        left: &Tree,
+        right: &Tree,
+        op: &str,
+        reg: &str,
+        check: Option<&Loc>,
+    ) -> String {
+        let mut buffer = String::new();
+        buffer += &format!("\t;; BinOp({:?} {op} {:?}) ;;\n", left, right);
+        let typ = match (op, check) {
+            ("div", _) | (_, Some(_)) => self.arith_type(left, right),
+            _ => Type::I64,
+        };
+        let signed = typ.is_signed();
+        buffer += &self.gen_operands(left, right);
+        match op {
+            "div" => {
+                if let Some(loc) = check {
+                    buffer += "\ttest rbx, rbx\n";
+                    let reason = Tree::String("attempt to divide by zero".to_string());
+                    buffer += &self.gen_check("jnz", &reason, loc);
+                    // i64::MIN / -1 doesn't fit and traps in idiv
+                    if signed && typ.size() == 8 {
+                        let ok = self.create_lb();
+                        buffer += "\tcmp rbx, -1\n";
+                        buffer += &format!("\tjne {ok}\n");
+                        buffer += "\tmov rcx, 0x8000000000000000\n";
+                        buffer += "\tcmp rax, rcx\n";
+                        let reason = Tree::String("attempt to divide with overflow".to_string());
+                        buffer += &self.gen_check("jne", &reason, loc);
+                        buffer += &format!("{ok}:\n");
+                    }
+                }
+                buffer += match signed {
+                    true => "\tcqo\n\tidiv rbx\n",
+                    false => "\txor rdx, rdx\n\tdiv rbx\n",
+                };
+            }
+            // sets the overflow flag when the product doesn't fit in 64 unsigned bits
+            "imul" if check.is_some() && !signed && typ.size() == 8 => buffer += "\tmul rbx\n",
+            _ => buffer += &format!("\t{} rax, rbx\n", op),
+        }
+        if let Some(loc) = check {
+            let reason = match op {
+                "add" => "attempt to add with overflow",
+                "sub" => "attempt to subtract with overflow",
+                "imul" => "attempt to multiply with overflow",
+                _ => "attempt to divide with overflow",
+            };
+            buffer += &self.gen_overflow_check(&typ, op, reason, loc);
+        }
+        if reg != "rax" {
+            buffer += &format!("\tmov {reg}, rax\n");
+        }
+        buffer += &format!("\t;; End BinOp({:?} {op} {:?}) ;;\n", left, right);
+        buffer
+    }
+
+    // 64 bit results are checked with the flags, narrower ones are computed in 64 bits and must fit
+    fn gen_overflow_check(&mut self, typ: &Type, op: &str, reason: &str, loc: &Loc) -> String {
+        let reason = Tree::String(reason.to_string());
+        let mut buffer = String::new();
+        let jump = match (typ.size(), typ.is_signed(), op) {
+            // checked before the division
+            (8, _, "div") | (_, false, "div") => return buffer,
+            (8, true, _) | (8, false, "imul") => "jno",
+            (8, false, _) => "jnc",
+            (4, true, _) => {
+                buffer += "\tmovsxd rcx, eax\n\tcmp rcx, rax\n";
+                "je"
+            }
+            (size, true, _) => {
+                let part = if size == 1 { "al" } else { "ax" };
+                buffer += &format!("\tmovsx rcx, {part}\n\tcmp rcx, rax\n");
+                "je"
+            }
+            (size, false, _) => {
+                buffer += &format!(
+                    "\tmov rcx, {}\n\tcmp rax, rcx\n",
+                    u64::MAX >> (64 - size * 8)
+                );
+                "jbe"
+            }
+        };
+        buffer += &self.gen_check(jump, &reason, loc);
+        buffer
+    }
+
+    // the int type arithmetic happens in, literals take the type of the other side
+    fn arith_type(&self, left: &Tree, right: &Tree) -> Type {
+        let typ = |tree: &Tree| match tree {
+            Tree::Number(_) => None,
+            _ => Some(self.type_of(tree)).filter(Type::is_int),
+        };
+        match (typ(left), typ(right)) {
+            (Some(ltyp), Some(rtyp)) if ltyp == rtyp => ltyp,
+            (Some(typ), None) if matches!(right, Tree::Number(_)) => typ,
+            (None, Some(typ)) if matches!(left, Tree::Number(_)) => typ,
+            _ => Type::I64,
+        }
+    }
+
+    // clamps to the bounds of the operands' type instead of wrapping
+    fn gen_saturating(&mut self, left: &Tree, right: &Tree, op: &str, reg: &str) -> String {
+        let typ = self.arith_type(left, right);
+        let bits = typ.size() * 8;
+        let done = self.create_lb();
+        let mut buffer = format!("\t;; Saturating({:?} {op} {:?}) ;;\n", left, right);
+        buffer += &self.gen_operands(left, right);
+        match (bits, typ.is_signed()) {
+            (64, true) => {
+                buffer += "\tmov rcx, rax\n";
+                buffer += &format!("\t{op} rax, rbx\n");
+                buffer += &format!("\tjno {done}\n");
+                // rcx gets the sign the result should have had
+                buffer += match op {
+                    "add" => "\tmov rcx, rbx\n",
+                    "sub" => "\tmov rcx, rbx\n\tnot rcx\n",
+                    _ => "\txor rcx, rbx\n",
+                };
+                buffer += "\tmov rax, 0x7fffffffffffffff\n";
+                buffer += "\ttest rcx, rcx\n";
+                buffer += &format!("\tjns {done}\n");
+                buffer += "\tmov rax, 0x8000000000000000\n";
+            }
+            // the carry says it went past 0 or u64::MAX, mul sets it when rdx is needed
+            (64, false) => {
+                buffer += &match op {
+                    "imul" => "\tmul rbx\n".to_string(),
+                    _ => format!("\t{op} rax, rbx\n"),
+                };
+                buffer += &format!("\tjnc {done}\n");
+                buffer += match op {
+                    "sub" => "\txor eax, eax\n",
+                    _ => "\tmov rax, -1\n",
+                };
+            }
+            // narrower operands can't overflow 64 bits, the result is clamped to the type's range
+            (_, true) => {
+                buffer += &format!("\t{op} rax, rbx\n");
+                buffer += &format!("\tmov rcx, {}\n", i64::MAX >> (64 - bits));
+                buffer += "\tcmp rax, rcx\n\tcmovg rax, rcx\n";
+                buffer += &format!("\tmov rcx, {}\n", i64::MIN >> (64 - bits));
+                buffer += "\tcmp rax, rcx\n\tcmovl rax, rcx\n";
+            }
+            (_, false) => {
+                buffer += &format!("\t{op} rax, rbx\n");
+                buffer += &match op {
+                    "sub" => "\txor ecx, ecx\n\tcmp rax, rcx\n\tcmovl rax, rcx\n".to_string(),
+                    _ => format!(
+                        "\tmov rcx, {}\n\tcmp rax, rcx\n\tcmova rax, rcx\n",
+                        u64::MAX >> (64 - bits)
+                    ),
+                };
+            }
+        }
+        buffer += &format!("{done}:\n");
+        if reg != "rax" {
+            buffer += &format!("\tmov {reg}, rax\n");
+        }
+        buffer
+    }
+
+    fn gen_bin_exp(&mut self, tree: &Tree, lreg: &str) -> String {
+        match tree {
+            Tree::BinOp(left, op, right, loc) => match op {
+                Token::Plus | Token::Minus => self.gen_ptr_arith(left, op, right, lreg, loc),
+                Token::Multiply => {
+                    let check = self.options.checked_arithmetic.then_some(loc);
+                    self.gen_bin_op(left, right, "imul", lreg, check)
+                }
+                Token::Divide => {
+                    let check = self.options.checked_arithmetic.then_some(loc);
+                    self.gen_bin_op(left, right, "div", lreg, check)
+                }
+                _ => panic!("invalid Token"),
+            },
+            _ => panic!("Expected BinOp Tree"),
+        }
+    }
+
+    // add/sub with the integer side scaled by the pointee size when a pointer is involved
+    fn gen_ptr_arith(
+        &mut self,
+        left: &Tree,
+        op: &Token,
+        right: &Tree,
+        reg: &str,
+        loc: &Loc,
+    ) -> String {
+        let op_str = match op {
+            Token::Plus => "add",
+            _ => "sub",
+        };
+        let check = self.options.checked_arithmetic.then_some(loc);
+        let scaled = |tree: &Tree, size: usize| match size {
+            1 => tree.clone(),
+            _ => Tree::BinOp(
+                Box::new(tree.clone()),
+                Token::Multiply,
+                Box::new(Tree::Number(size)),
+                *loc,
+            ),
+        };
+        let (ltyp, rtyp) = (self.type_of(left).decay(), self.type_of(right).decay());
+        match (ltyp.pointee(), rtyp.pointee()) {
+            (Some(pointee), Some(_)) => {
+                let size = pointee.size();
+                let mut buffer = self.gen_bin_op(left, right, "sub", "rax", check);
+                if size != 1 {
+                    buffer += &format!("\tmov rbx, {size}\n");
+                    buffer += "\tcqo\n";
+                    buffer += "\tidiv rbx\n";
+                }
+                if reg != "rax" {
+                    buffer += &format!("\tmov {reg}, rax\n");
+                }
+                buffer
+            }
+            (Some(pointee), None) => {
+                self.gen_bin_op(left, &scaled(right, pointee.size()), op_str, reg, check)
+            }
+            (None, Some(pointee)) => {
+                self.gen_bin_op(&scaled(left, pointee.size()), right, op_str, reg, check)
+            }
+            (None, None) => self.gen_bin_op(left, right, op_str, reg, check),
+        }
+    }
+
+    fn gen_cmp_op(&mut self, left: &Tree, op: &str, right: &Tree) -> String {
+        if self.is_text_cmp(left, right) {
+            return self.gen_str_cmp(left, op, right);
+        }
+        // unsigned ints and pointers are ordered by the unsigned condition codes
+        let unsigned = [left, right]
+            .iter()
+            .any(|side| !matches!(side, Tree::Null) && !self.type_of(side).is_signed());
+        let op = match op {
+            "setg" if unsigned => "seta",
+            "setge" if unsigned => "setae",
+            "setl" if unsigned => "setb",
+            "setle" if unsigned => "setbe",
+            _ => op,
+        };
+        let mut buffer = self.gen_operands(left, right);
+        buffer += "\tcmp rax, rbx\n";
+        buffer += &format!("\t{} al\n", op);
+        buffer += "\tmovzx rax, al\n";
+        buffer
+    }
+
+    // text compares by content, with any other kind of text too
+    fn is_text_cmp(&self, left: &Tree, right: &Tree) -> bool {
+        // null has no type of its own
+        let typ = |tree: &Tree| (!matches!(tree, Tree::Null)).then(|| self.type_of(tree));
+        match (typ(left), typ(right)) {
+            (Some(ltyp), Some(rtyp)) if ltyp.is_text() && rtyp.is_text() => true,
+            (Some(typ), None) | (None, Some(typ)) if typ.is_text() => {
+                panic!(
+                    "Can't compare {:?} with null, only pointers can be null",
+                    typ
+                )
+            }
+            (Some(ltyp), Some(rtyp)) if ltyp.is_text() || rtyp.is_text() => {
+                panic!("Can't compare {:?} with {:?}", ltyp, rtyp)
+            }
+            _ => false,
+        }
+    }
+
+    // strings compare by content, ptr_eq compares where they are
+    fn gen_str_cmp(&mut self, left: &Tree, op: &str, right: &Tree) -> String {
+        let routine = match op {
+            "sete" | "setne" => {
+                self.use_runtime(runtime::STR_EQ);
+                "__str_eq"
+            }
+            _ => {
+                self.use_runtime(runtime::STR_CMP);
+                "__str_cmp"
+            }
+        };
+        let tmp = self.alloc_slots(2);
+        let mut buffer = self.gen_fat(left);
+        buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+        buffer += &format!("\tmov QWORD {}, rdx\n", self.stack_addr(tmp + 1));
+        buffer += &self.gen_fat(right);
+        buffer += "\tmov rdi, rax\n\tmov rcx, rdx\n";
+        buffer += &format!("\tmov rsi, QWORD {}\n", self.stack_addr(tmp));
+        buffer += &format!("\tmov rdx, QWORD {}\n", self.stack_addr(tmp + 1));
+        self.stack -= 2;
+        buffer += &format!("\tcall {routine}\n");
+        buffer += "\ttest rax, rax\n";
+        buffer += &format!("\t{} al\n", op);
+        buffer += "\tmovzx rax, al\n";
+        buffer
+    }
+
+    fn gen_cmp_exp(&mut self, tree: &Tree) -> String {
+        //[TODO] Adding the rest of CmpOp
+        match tree {
+            Tree::CmpOp(left, op, right) => {
+                let op_str = match op {
+                    Token::EquEqu => "sete",
+                    Token::NotEqu => "setne",
+                    Token::Greater => "setg",
+                    Token::GreatEqu => "setge",
+                    Token::Less => "setl",
+                    Token::LessEqu => "setle",
+                    _ => "",
+                };
+                self.gen_cmp_op(left, op_str, right)
+            }
+            // bools are already 0 or 1
+            _ if self.type_of(tree) == Type::Bool => self.gen_expr(tree, "rax"),
+            _ => panic!("Expected a condition, found {:?}", tree),
+        }
+    }
+
+    // what gen_cmp_exp rejects, without generating anything
+    fn check_condition(&self, tree: &Tree) {
+        match tree {
+            Tree::CmpOp(left, _, right) => {
+                self.is_text_cmp(left, right);
+            }
+            _ if self.type_of(tree) == Type::Bool => (),
+            _ => panic!("Expected a condition, found {:?}", tree),
+        }
+    }
+
+    fn gen_if_cmp(&mut self, expr: &Tree, next_case: &usize) -> String {
+        let mut buffer = String::new();
+        buffer += &self.gen_cmp_exp(expr);
+        buffer += "\ttest al, 1\n";
+        buffer += &format!("\tjz .LB{}\n", next_case);
+        buffer
+    }
+
+    fn handle_vars(&mut self, ident: &String, expr: &Tree) -> String {
+        let var = self.find_var(ident).clone();
+        self.gen_init(&var.typ.clone(), expr, &Dest::Var(var), 0)
+    }
+
+    // writes expr as a value of `typ` at `offset` bytes into dest
+    fn gen_init(&mut self, typ: &Type, expr: &Tree, dest: &Dest, offset: usize) -> String {
+        let mut buffer = String::new();
+        match (typ, expr) {
+            (Type::Array(elem, _), Tree::Array(elems)) => {
+                for (i, value) in elems.iter().enumerate() {
+                    buffer += &self.gen_init(elem, value, dest, offset + i * elem.size());
+                }
+            }
+            (Type::Struct { fields, .. }, Tree::StructLit(_, values)) => {
+                if values.len() < fields.len() {
+                    buffer += &self.gen_zero(typ, dest, offset);
+                }
+                for (field, value) in values {
+                    let (field_offset, field_typ) = self.struct_field(typ, field);
+                    buffer += &self.gen_init(&field_typ, value, dest, offset + field_offset);
+                }
+            }
+            (Type::Enum { .. }, Tree::Variant(_, name, args)) if typ.is_aggregate() => {
+                let variant = self.enum_variant(typ, name, args);
+                buffer += &format!("\tmov rax, {}\n", variant.tag);
+                let (setup, addr) = self.dest_addr(dest, offset, "rbx");
+                buffer += &setup;
+                buffer += &self.store(&Type::U64, &addr);
+                for ((field_offset, field_typ), arg) in
+                    variant.offsets().into_iter().zip(&variant.fields).zip(args)
+                {
+                    buffer += &self.gen_init(field_typ, arg, dest, offset + field_offset);
+                }
+            }
+            (_, Tree::Empty()) if typ.is_aggregate() => buffer += &self.gen_zero(typ, dest, offset),
+            _ if typ.is_aggregate() => {
+                buffer += &self.gen_expr(expr, "rsi");
+                let (setup, addr) = self.dest_addr(dest, offset, "rdi");
+                buffer += &setup;
+                buffer += &format!("\tlea rdi, [{addr}]\n");
+                buffer += &format!("\tmov rcx, {}\n", typ.size());
+                buffer += "\trep movsb\n";
+            }
+            _ => {
+                buffer += &match expr {
+                    Tree::Empty() if !typ.is_fat() => "\txor eax, eax\n".to_string(),
+                    _ => self.gen_value(expr, typ),
+                };
+                let (setup, addr) = self.dest_addr(dest, offset, "rbx");
+                buffer += &setup;
+                buffer += &self.store(typ, &addr);
+            }
+        }
+        buffer
+    }
+
+    fn gen_match(
+        &mut self,
+        expr: &Tree,
+        arms: &[Arm],
+        end: &usize,
+        loc: &Loc,
+        value: Option<&Type>,
+    ) -> String {
+        let mut buffer = String::new();
+        let typ = self.type_of(expr);
+        let enum_name = match &typ {
+            Type::Enum { name, .. } => Some(name),
+            _ if typ.is_int() => None,
+            _ => panic!("Can't match on {:?} of type {:?}", expr, typ),
+        };
+        // payload enums are matched through their address, scalars directly
+        let tmp = self.alloc_temp();
+        buffer += &self.gen_expr(expr, "rax");
+        if typ.is_aggregate() {
+            buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+            buffer += "\tmov rax, QWORD [rax]\n";
+        }
+
+        // every pattern is a set of tag or integer values [start, end)
+        let mut cases: Vec<(usize, usize, usize)> = vec![];
+        let mut default = None;
+        for arm in arms {
+            let at = format!("{}:{}", self.options.file_name, arm.loc);
+            let (start, stop) = match (&arm.pattern, enum_name) {
+                (Pattern::Wildcard, _) => {
+                    if default.is_some() || self.covers(&typ, &cases) {
+                        eprintln!("warning: unreachable match arm at {at}");
+                    }
+                    default.get_or_insert(arm.label);
+                    continue;
+                }
+                (Pattern::Number(value), None) => (*value, value + 1),
+                (Pattern::Range(start, stop), None) if start < stop => (*start, *stop),
+                (Pattern::Range(start, stop), None) => {
+                    panic!("empty range pattern {}..{} at {}", start, stop, at)
+                }
+                (Pattern::Variant(name, variant, bindings), Some(enum_name))
+                    if name == enum_name =>
+                {
+                    let variant = typ.variant(variant).unwrap_or_else(|| {
+                        panic!("enum {} has no variant {} at {}", name, variant, at)
+                    });
+                    if bindings.len() != variant.fields.len() {
+                        panic!(
+                            "{}.{} has {} fields, found {} bindings at {}",
+                            name,
+                            variant.name,
+                            variant.fields.len(),
+                            bindings.len(),
+                            at
+                        );
+                    }
+                    (variant.tag, variant.tag + 1)
+                }
+                (pattern, _) => panic!(
+                    "{:?} can't match {:?} of type {:?} at {}",
+                    pattern, expr, typ, at
+                ),
+            };
+            if default.is_some() || Self::covered(&cases, start, stop) {
+                eprintln!("warning: unreachable match arm at {at}");
+            }
+            cases.push((start, stop, arm.label));
+        }
+        let default = match default {
+            Some(label) => label,
+            None if self.covers(&typ, &cases) => *end,
+            None => match &typ {
+                Type::Enum { name, variants } => {
+                    let missing: Vec<String> = variants
+                        .iter()
+                        .filter(|v| !Self::covered(&cases, v.tag, v.tag + 1))
+                        .map(|v| format!("{}.{}", name, v.name))
+                        .collect();
+                    panic!(
+                        "non-exhaustive match at {}:{}, {} not covered",
+                        self.options.file_name,
+                        loc,
+                        missing.join(", ")
+                    );
+                }
+                _ => panic!(
+                    "non-exhaustive match on {:?} at {}:{}, add a `_` arm",
+                    typ, self.options.file_name, loc
+                ),
+            },
+        };
+        buffer += &self.gen_dispatch(&cases, default);
+
+        for arm in arms {
+            buffer += &format!(".LB{}:\n", arm.label);
+            self.begin_scope();
+            if let Pattern::Variant(_, variant, bindings) = &arm.pattern {
+                let variant = typ.variant(variant).unwrap().clone();
+                for ((binding, field_typ), offset) in
+                    bindings.iter().zip(&variant.fields).zip(variant.offsets())
+                {
+                    if binding == "_" {
+                        continue;
+                    }
+                    buffer += &self.bind_field(binding, field_typ, tmp, offset);
+                }
+            }
+            buffer += &self.gen_body(&arm.body, value);
+            buffer += &self.end_scope();
+            buffer += &format!("\tjmp .LB{}\n", end);
+        }
+        buffer += &format!(".LB{}:\n", end);
+        self.free_temp();
+        buffer
+    }
+
+    // jumps from the value in rax to the label of the first case that holds it
+    fn gen_dispatch(&mut self, cases: &[(usize, usize, usize)], default: usize) -> String {
+        let mut buffer = String::new();
+        let low = cases.iter().map(|case| case.0).min().unwrap_or(0);
+        let high = cases.iter().map(|case| case.1).max().unwrap_or(0);
+        let count: usize = cases.iter().map(|(start, stop, _)| stop - start).sum();
+        // dense cases go through a jump table
+        if cases.len() >= 4 && high - low <= 256 && high - low <= count * 2 {
+            let table = self.create_lb();
+            if low != 0 {
+                buffer += &format!("\tsub rax, {low}\n");
+            }
+            buffer += &format!("\tcmp rax, {}\n", high - low);
+            buffer += &format!("\tjae .LB{default}\n");
+            buffer += &format!("\tjmp [{table} + rax*8]\n");
+            let labels: Vec<String> = (low..high)
+                .map(|value| {
+                    let label = cases
+                        .iter()
+                        .find(|(start, stop, _)| (*start..*stop).contains(&value))
+                        .map_or(default, |case| case.2);
+                    format!(".LB{label}")
+                })
+                .collect();
+            buffer += &format!("{table}:\n\tdq {}\n", labels.join(", "));
+            return buffer;
+        }
+        for (start, stop, label) in cases {
+            // cmp only takes 32 bit immediates
+            if stop - start == 1 {
+                buffer += &format!("\tmov rbx, {start}\n");
+                buffer += "\tcmp rax, rbx\n";
+                buffer += &format!("\tje .LB{label}\n");
+            } else {
+                buffer += &format!("\tmov rbx, {start}\n");
+                buffer += "\tmov rcx, rax\n";
+                buffer += "\tsub rcx, rbx\n";
+                buffer += &format!("\tmov rbx, {}\n", stop - start);
+                buffer += "\tcmp rcx, rbx\n";
+                buffer += &format!("\tjb .LB{label}\n");
+            }
+        }
+        buffer += &format!("\tjmp .LB{default}\n");
+        buffer
+    }
+
+    // copies a payload field of the enum whose address is in `tmp` into a new variable
+    fn bind_field(&mut self, binding: &str, typ: &Type, tmp: usize, offset: usize) -> String {
+        let mut buffer = String::new();
+        let stack_loc = self.alloc_slots(typ.slots());
+        let var = Var::new(binding.to_string(), Storage::Stack(stack_loc), typ.clone());
+        buffer += &format!("\tmov rbx, QWORD {}\n", self.stack_addr(tmp));
+        match typ {
+            _ if typ.is_aggregate() => {
+                buffer += &format!("\tlea rsi, [rbx + {offset}]\n");
+                buffer += &format!("\tlea rdi, {}\n", self.var_addr(&var));
+                buffer += &format!("\tmov rcx, {}\n", typ.size());
+                buffer += "\trep movsb\n";
+            }
+            _ if typ.is_fat() => {
+                buffer += &format!("\tmov rax, QWORD [rbx + {offset}]\n");
+                buffer += &format!("\tmov rdx, QWORD [rbx + {}]\n", offset + 8);
+                buffer += &self.store(typ, &self.var_ref(&var, 0));
+            }
+            _ => {
+                buffer += &self.load(typ, "rax", &format!("rbx + {offset}"));
+                buffer += &self.store(typ, &self.var_ref(&var, 0));
+            }
+        }
+        self.vars.push(var);
+        buffer
+    }
+
+    // whether the cases leave no value of `typ` unmatched
+    fn covers(&self, typ: &Type, cases: &[(usize, usize, usize)]) -> bool {
+        match typ {
+            Type::Enum { variants, .. } => variants
+                .iter()
+                .all(|v| Self::covered(cases, v.tag, v.tag + 1)),
+            Type::U8 | Type::U16 | Type::U32 => Self::covered(cases, 0, 1 << (typ.size() * 8)),
+            _ => false,
+        }
+    }
+
+    fn covered(cases: &[(usize, usize, usize)], start: usize, stop: usize) -> bool {
+        let mut pos = start;
+        while pos < stop {
+            match cases
+                .iter()
+                .find(|(start, stop, _)| (*start..*stop).contains(&pos))
+            {
+                Some((_, end, _)) => pos = *end,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    fn enum_variant(&self, typ: &Type, name: &str, args: &[Tree]) -> Variant {
+        let variant = typ
+            .variant(name)
+            .unwrap_or_else(|| panic!("{:?} has no variant {}", typ, name));
+        if variant.fields.len() != args.len() {
+            panic!(
+                "{} takes {} values, found {}",
+                name,
+                variant.fields.len(),
+                args.len()
+            );
+        }
+        variant.clone()
+    }
+
+    fn gen_zero(&mut self, typ: &Type, dest: &Dest, offset: usize) -> String {
+        let (mut buffer, addr) = self.dest_addr(dest, offset, "rdi");
+        buffer += &format!("\tlea rdi, [{addr}]\n");
+        buffer += "\txor eax, eax\n";
+        buffer += &format!("\tmov rcx, {}\n", typ.size());
+        buffer += "\trep stosb\n";
+        buffer
+    }
+
+    // an address expression for dest, and the code that sets up `reg` when it needs one
+    fn dest_addr(&self, dest: &Dest, offset: usize, reg: &str) -> (String, String) {
+        match dest {
+            Dest::Var(var) => (String::new(), self.var_ref(var, offset)),
+            Dest::Temp(tmp) => {
+                let setup = format!("\tmov {reg}, QWORD {}\n", self.stack_addr(*tmp));
+                match offset {
+                    0 => (setup, reg.to_string()),
+                    _ => (setup, format!("{reg} + {offset}")),
+                }
+            }
+        }
+    }
+
+    fn step_var(&mut self, ident: &String, step: &str, op: &str) -> String {
+        let var = self.find_var(ident);
+        let addr = self.var_addr(var);
+        match var.typ.pointee() {
+            Some(pointee) if pointee.size() != 1 => {
+                format!("\t{op} QWORD {addr}, {}\n", pointee.size())
+            }
+            _ => format!("\t{step} {} {addr}\n", Self::size_kw(&var.typ)),
+        }
+    }
+
+    // loads a value of `typ` from `[addr]`, extending it to 64 bits
+    fn load(&self, typ: &Type, reg: &str, addr: &str) -> String {
+        let signed = typ.is_signed();
+        match typ.size() {
+            1 | 2 if signed => format!("\tmovsx {reg}, {} [{addr}]\n", Self::size_kw(typ)),
+            1 | 2 => format!("\tmovzx {reg}, {} [{addr}]\n", Self::size_kw(typ)),
+            4 if signed => format!("\tmovsxd {reg}, DWORD [{addr}]\n"),
+            // writing the 32 bit register clears the upper half
+            4 => format!("\tmov {}, DWORD [{addr}]\n", Self::reg32(reg)),
+            _ => format!("\tmov {reg}, QWORD [{addr}]\n"),
+        }
+    }
+
+    // stores rax (and rdx) as a value of `typ` at `[addr]`
+    fn store(&self, typ: &Type, addr: &str) -> String {
+        match typ.size() {
+            _ if typ.is_fat() => {
+                format!("\tmov QWORD [{addr}], rax\n\tmov QWORD [{addr} + 8], rdx\n")
+            }
+            1 => format!("\tmov BYTE [{addr}], al\n"),
+            2 => format!("\tmov WORD [{addr}], ax\n"),
+            4 => format!("\tmov DWORD [{addr}], eax\n"),
+            _ => format!("\tmov QWORD [{addr}], rax\n"),
+        }
+    }
+
+    fn size_kw(typ: &Type) -> &'static str {
+        match typ.size() {
+            1 => "BYTE",
+            2 => "WORD",
+            4 => "DWORD",
+            _ => "QWORD",
+        }
+    }
+
+    fn reg32(reg: &str) -> String {
+        match reg.strip_prefix('r') {
+            Some(num) if num.starts_with(|c: char| c.is_ascii_digit()) => format!("{reg}d"),
+            Some(name) => format!("e{name}"),
+            None => reg.to_string(),
+        }
+    }
+
+    // the type of the last expression of a block, with the block's own lets in scope
+    fn block_type(&self, body: &[Tree], bindings: Vec<(String, Type)>) -> Type {
+        let outer = self.block_vars.borrow().len();
+        for (name, typ) in bindings {
+            self.block_vars
+                .borrow_mut()
+                .push(Var::new(name, Storage::Stack(0), typ));
+        }
+        let Some((last, stmts)) = body.split_last() else {
+            panic!("Expected a value, found an empty block");
+        };
+        for stmt in stmts {
+            if let Tree::Let(name, typ, expr) = stmt {
+                let typ = typ.clone().unwrap_or_else(|| self.type_of(expr));
+                let var = Var::new(name.to_string(), Storage::Stack(0), typ);
+                self.block_vars.borrow_mut().push(var);
+            }
+        }
+        let typ = self.type_of(last);
+        self.block_vars.borrow_mut().truncate(outer);
+        typ
+    }
+
+    fn type_of(&self, tree: &Tree) -> Type {
+        match tree {
+            Tree::Number(_)
+            | Tree::CmpOp(_, Token::DDot, _)
+            | Tree::Write(..)
+            | Tree::SysCall(..) => Type::I64,
+            Tree::CmpOp(..) | Tree::Bool(_) => Type::Bool,
+            Tree::Ident(var) => match self
+                .block_vars
+                .borrow()
+                .iter()
+                .rev()
+                .find(|v| v.name == *var)
+            {
+                Some(var) => var.typ.clone(),
+                None => self.find_var(var).typ.clone(),
+            },
+            Tree::String(_) | Tree::Format(..) => Type::Str,
+            Tree::Call(name, ..) => match self.find_fn(name) {
+                Some(function) => function.ret.clone(),
+                None => Self::builtin_type(name),
+            }
+            .unwrap_or_else(|| panic!("{} doesn't return a value", name)),
+            Tree::Method(base, method, ..) => {
+                let typ = self.type_of(base);
+                match (Self::vec_elem(&typ), method.as_str()) {
+                    (Some(elem), "pop") => elem,
+                    (Some(Type::U8), "as_str") => Type::Str,
+                    _ => panic!(
+                        "{:?} of type {:?} has no method {} with a value",
+                        base, typ, method
+                    ),
+                }
+            }
+            Tree::If { body, .. } => self.block_type(body, vec![]),
+            Tree::Match { expr, arms, .. } => {
+                let typ = self.type_of(expr);
+                // arms that only exit don't have a type
+                let arm = arms
+                    .iter()
+                    .find(|arm| !arm.body.last().is_some_and(|last| self.diverges(last)))
+                    .unwrap_or_else(|| panic!("match used as a value never produces one"));
+                let bindings = match &arm.pattern {
+                    Pattern::Variant(_, variant, bindings) => match typ.variant(variant) {
+                        Some(variant) => bindings
+                            .iter()
+                            .cloned()
+                            .zip(variant.fields.clone())
+                            .collect(),
+                        None => vec![],
+                    },
+                    _ => vec![],
+                };
+                self.block_type(&arm.body, bindings)
+            }
+            Tree::AddrOf(expr) => Type::Ptr(Box::new(self.type_of(expr))),
+            Tree::Deref(expr) => match self.type_of(expr).decay() {
+                Type::Ptr(typ) => *typ,
+                typ => panic!("Can't dereference {:?} of type {:?}", expr, typ),
+            },
+            Tree::Index(base, index, _) => match (self.type_of(base), &**index) {
+                (Type::Str, Tree::CmpOp(_, Token::DDot, _)) => Type::Str,
+                (
+                    Type::Array(typ, _) | Type::Slice(typ) | Type::Vec(typ),
+                    Tree::CmpOp(_, Token::DDot, _),
+                ) => Type::Slice(typ),
+                (typ, _) => typ
+                    .elem()
+                    .unwrap_or_else(|| panic!("Can't index into {:?} of type {:?}", base, typ)),
+            },
+            Tree::Field(base, field) => match (self.type_of(base), field.as_str()) {
+                (typ, _) if typ.as_struct().is_some() => self.struct_field(&typ, field).1,
+                (_, "len") => Type::I64,
+                (typ, "ptr") if typ.is_fat() => typ.decay(),
+                (typ, "ptr") if Self::vec_elem(&typ).is_some() => {
+                    Type::Ptr(Box::new(Self::vec_elem(&typ).unwrap()))
+                }
+                (typ, _) => panic!("{:?} of type {:?} has no field {}", base, typ, field),
+            },
+            Tree::StructLit(typ, _) | Tree::Variant(typ, ..) => typ.clone(),
+            Tree::Array(elems) => match elems.first() {
+                Some(elem) => Type::Array(Box::new(self.type_of(elem)), elems.len()),
+                None => panic!("Can't infer the type of [], add a type annotation"),
+            },
+            Tree::BinOp(left, Token::Plus, right, _) if self.is_concat(left, right) => Type::Str,
+            Tree::BinOp(left, op, right, _) => {
+                let (ltyp, rtyp) = (self.type_of(left).decay(), self.type_of(right).decay());
+                match (op, &ltyp, &rtyp) {
+                    (Token::Minus, Type::Ptr(_), Type::Ptr(_)) if ltyp == rtyp => Type::I64,
+                    (Token::Plus | Token::Minus, Type::Ptr(_), _) if rtyp.is_int() => ltyp,
+                    (Token::Plus, _, Type::Ptr(_)) if ltyp.is_int() => rtyp,
+                    (_, Type::Ptr(_), _) | (_, _, Type::Ptr(_)) => {
+                        panic!("Invalid pointer arithmetic {:?} {:?} {:?}", ltyp, op, rtyp)
+                    }
+                    _ => Type::I64,
+                }
+            }
+            Tree::Null => panic!("Can't infer the type of null, add a type annotation"),
+            _ => panic!("Expected expression, found {:?}", tree),
+        }
+    }
+
+    fn check_types(&self, expected: &Type, expr: &Tree) {
+        match (expr, expected) {
+            (Tree::Null, Type::Ptr(_)) | (Tree::Empty(), _) => return,
+            // the heap hands out untyped memory, like null
+            (Tree::Call(name, ..), Type::Ptr(_))
+                if matches!(name.as_str(), "alloc" | "realloc") && self.find_fn(name).is_none() =>
+            {
+                return
+            }
+            (Tree::Array(elems), Type::Array(elem, len)) => {
+                if elems.len() != *len {
+                    panic!(
+                        "Expected {} elements, found {} in {:?}",
+                        len,
+                        elems.len(),
+                        expr
+                    );
+                }
+                return elems.iter().for_each(|value| self.check_types(elem, value));
+            }
+            (Tree::Variant(typ, name, args), Type::Enum { .. }) if typ == expected => {
+                let variant = self.enum_variant(typ, name, args);
+                return variant
+                    .fields
+                    .iter()
+                    .zip(args)
+                    .for_each(|(field, arg)| self.check_types(field, arg));
+            }
+            (Tree::StructLit(typ, values), Type::Struct { .. }) if typ == expected => {
+                return values.iter().for_each(|(field, value)| {
+                    self.check_types(&self.struct_field(typ, field).1, value)
+                });
+            }
+            _ => (),
+        }
+        let found = self.type_of(expr);
+        let decays = matches!(expected, Type::Ptr(_)) && found.decay() == *expected;
+        // arrays coerce to slices of their elements, a str doesn't since []u8 can be written
+        let slices = match (expected, &found) {
+            (Type::Slice(elem), Type::Array(..)) => found.elem().as_ref() == Some(elem),
+            (Type::Str, Type::Slice(elem) | Type::Vec(elem)) => **elem == Type::U8,
+            (Type::Slice(elem), Type::Vec(found)) => elem == found,
+            _ => false,
+        };
+        let ints = expected.is_int() && found.is_int();
+        if !(ints || *expected == found || decays || slices) {
+            panic!(
+                "Mismatched types: expected {:?}, found {:?} in {:?}",
+                expected, found, expr
+            );
+        }
+    }
+
+    // expressions that load straight into a register without touching others
+    fn is_simple(tree: &Tree) -> bool {
+        match tree {
+            Tree::Number(_) | Tree::Bool(_) | Tree::Ident(_) | Tree::String(_) | Tree::Null => true,
+            Tree::Field(expr, _) => matches!(**expr, Tree::Ident(_)),
+            _ => false,
+        }
+    }
+
+    fn var_addr(&self, var: &Var) -> String {
+        self.var_addr_off(var, 0)
+    }
+
+    fn var_addr_off(&self, var: &Var, offset: usize) -> String {
+        format!("[{}]", self.var_ref(var, offset))
+    }
+
+    // the address expression of a variable, without brackets
+    fn var_ref(&self, var: &Var, offset: usize) -> String {
+        match &var.storage {
+            Storage::Stack(stack_loc) => format!("rsp + {}", stack_loc * 8 + offset),
+            Storage::Global(label) | Storage::ReadOnly(label) if offset == 0 => label.to_string(),
+            Storage::Global(label) | Storage::ReadOnly(label) => format!("{label} + {offset}"),
+            Storage::Const(_) => panic!("Can't take the address of const {}", var.name),
+        }
+    }
+
+    fn struct_field(&self, typ: &Type, field: &str) -> (usize, Type) {
+        let typ = typ.as_struct().unwrap_or(typ);
+        match typ {
+            Type::Struct { name, .. } => typ
+                .field(field)
+                .unwrap_or_else(|| panic!("struct {} has no field {}", name, field)),
+            _ => panic!("{:?} is not a struct", typ),
+        }
+    }
+
+    // lvalues whose value is loaded from memory, as opposed to str/slice fields like .len
+    fn is_place(&self, tree: &Tree) -> bool {
+        match tree {
+            Tree::Field(base, _) => self.type_of(base).as_struct().is_some(),
+            _ => true,
+        }
+    }
+
+    fn stack_addr(&self, stack_loc: usize) -> String {
+        self.stack_addr_off(stack_loc, 0)
+    }
+
+    fn stack_addr_off(&self, stack_loc: usize, offset: usize) -> String {
+        format!("[rsp + {}]", stack_loc * 8 + offset)
+    }
+
+    fn alloc_slots(&mut self, count: usize) -> usize {
+        self.stack += count;
+        self.max_stack = self.max_stack.max(self.stack);
+        self.stack - count
+    }
+
+    fn alloc_temp(&mut self) -> usize {
+        self.alloc_slots(1)
+    }
+
+    fn free_temp(&mut self) {
+        self.stack -= 1;
+    }
+
+    // labels made by the generator itself, the parser owns .LB
+    fn create_lb(&mut self) -> String {
+        self.lb_count += 1;
+        format!(".LG{}", self.lb_count)
+    }
+
+    fn use_runtime(&mut self, routine: &'static str) {
+        if !self.runtime.contains(&routine) {
+            self.runtime.push(routine);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(self.vars.len())
+    }
+    fn end_scope(&mut self) -> String {
+        // deferred blocks still see the vars of the scope
+        let buffer = self.run_defers(self.scopes.len(), true);
+        let pop_count = self.vars.len() - self.scopes.last().unwrap();
+        // not used for now
+        // let mut buffer = String::new();
+        // buffer += &format!("\tadd rsp, {}\n", pop_count * 8);
+        // statics only live at the top level so scopes only ever pop stack vars
+        if let Some(Storage::Stack(stack_loc)) = self
+            .vars
+            .get(self.vars.len() - pop_count)
+            .map(|var| &var.storage)
+        {
+            self.stack = *stack_loc;
+        }
+        for _ in 0..pop_count {
+            self.vars.pop();
         }
         self.scopes.pop();
-        // buffer
+        buffer
     }
-    fn create_scope(&mut self, body: &Vec<Tree>) -> String {
+    fn create_scope(&mut self, body: &[Tree]) -> String {
         let mut buffer = String::new();
         self.begin_scope();
         body.iter().for_each(|stmt| {
             buffer += &self.gen_linux_64_program(stmt);
         });
-        self.end_scope();
+        buffer += &self.end_scope();
+        buffer
+    }
+    fn create_loop_scope(&mut self, body: &[Tree]) -> String {
+        self.loops.push(self.scopes.len() + 1);
+        let buffer = self.create_scope(body);
+        self.loops.pop();
+        buffer
+    }
+
+    // keeps the value in rax and rdx away from the deferred blocks
+    fn run_defers_around_value(&mut self, depth: usize, drain: bool) -> String {
+        let mut buffer = String::new();
+        if self.defers.last().is_none_or(|d| d.depth < depth) {
+            return buffer;
+        }
+        let tmp = self.alloc_slots(2);
+        buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+        buffer += &format!("\tmov QWORD {}, rdx\n", self.stack_addr(tmp + 1));
+        buffer += &self.run_defers(depth, drain);
+        buffer += &format!("\tmov rax, QWORD {}\n", self.stack_addr(tmp));
+        buffer += &format!("\tmov rdx, QWORD {}\n", self.stack_addr(tmp + 1));
+        self.stack -= 2;
+        buffer
+    }
+
+    // emits the blocks deferred at `depth` or deeper, latest first
+    // `drain` is for scopes that actually end, early exits leave them pending
+    fn run_defers(&mut self, depth: usize, drain: bool) -> String {
+        let mut buffer = String::new();
+        let start = self
+            .defers
+            .iter()
+            .position(|d| d.depth >= depth)
+            .unwrap_or(self.defers.len());
+        let pending = self.defers.split_off(start);
+        for deferred in pending.iter().rev() {
+            buffer += "\t;; Defer ;;\n";
+            // vars declared after the defer aren't visible to it
+            let later = self.vars.split_off(deferred.vars);
+            buffer += &self.create_scope(&deferred.body);
+            self.vars.extend(later);
+            buffer += "\t;; End Defer ;;\n";
+        }
+        if !drain {
+            self.defers.extend(pending);
+        }
+        buffer
+    }
+    // the type, and whether the initializer fits it, of a top level static or const
+    fn declare_global(
+        &mut self,
+        keyword: &str,
+        ident: &String,
+        typ: &Option<Type>,
+        expr: &Tree,
+    ) -> Type {
+        if !self.scopes.is_empty() {
+            panic!("{} {} must be declared at the top level", keyword, ident);
+        }
+        if self
+            .vars
+            .iter()
+            .chain(&self.globals)
+            .any(|var| var.name == *ident)
+        {
+            panic!("{} is already declared", ident);
+        }
+        match typ {
+            Some(typ) => {
+                self.check_types(typ, expr);
+                typ.clone()
+            }
+            None => self.type_of(expr),
+        }
+    }
+
+    // the initial value of a global as data directives
+    fn gen_data(&mut self, ident: &String, typ: &Type, expr: &Tree) -> String {
+        match (typ, expr) {
+            (Type::Array(elem, _), Tree::Array(elems)) => elems
+                .iter()
+                .map(|value| self.gen_data(ident, elem, value))
+                .collect(),
+            (Type::Struct { fields, .. }, Tree::StructLit(_, values)) => {
+                let fields = fields
+                    .iter()
+                    .map(|(field, field_typ)| {
+                        let value = values.iter().find(|(name, _)| name == field);
+                        let (offset, _) = self.struct_field(typ, field);
+                        (
+                            offset,
+                            field_typ.clone(),
+                            value.map(|(_, value)| value.clone()),
+                        )
+                    })
+                    .collect();
+                self.gen_data_fields(ident, typ.size(), fields)
+            }
+            (Type::Enum { .. }, Tree::Variant(_, name, args)) => {
+                let variant = self.enum_variant(typ, name, args);
+                let mut fields = vec![(0, Type::U64, Some(Tree::Number(variant.tag)))];
+                for ((offset, field_typ), arg) in
+                    variant.offsets().into_iter().zip(&variant.fields).zip(args)
+                {
+                    fields.push((offset, field_typ.clone(), Some(arg.clone())));
+                }
+                self.gen_data_fields(ident, typ.size(), fields)
+            }
+            (Type::Str | Type::Slice(_), Tree::String(string) | Tree::Format(_, string)) => {
+                let str = self.intern(string);
+                format!("\tdq str_{}, {}\n", str.loc, str.len)
+            }
+            (_, Tree::Null) => "\tdq 0\n".to_string(),
+            _ => match self.eval_const(expr) {
+                Some(value) => format!("\t{} {}\n", Self::data_directive(typ), value),
+                None => panic!(
+                    "{} must be initialized with a constant, found {:?}",
+                    ident, expr
+                ),
+            },
+        }
+    }
+
+    // fields at their offsets, with the padding between them and missing fields zeroed
+    fn gen_data_fields(
+        &mut self,
+        ident: &String,
+        size: usize,
+        fields: Vec<(usize, Type, Option<Tree>)>,
+    ) -> String {
+        let mut buffer = String::new();
+        let mut end = 0;
+        for (offset, typ, value) in fields {
+            if offset > end {
+                buffer += &format!("\ttimes {} db 0\n", offset - end);
+            }
+            buffer += &match value {
+                Some(value) => self.gen_data(ident, &typ, &value),
+                None => format!("\ttimes {} db 0\n", typ.size()),
+            };
+            end = offset + typ.size();
+        }
+        if size > end {
+            buffer += &format!("\ttimes {} db 0\n", size - end);
+        }
+        buffer
+    }
+
+    fn data_directive(typ: &Type) -> &'static str {
+        match typ.size() {
+            1 => "db",
+            2 => "dw",
+            4 => "dd",
+            _ => "dq",
+        }
+    }
+
+    // folds a constant expression into a literal
+    fn fold(&self, tree: &Tree) -> Option<Tree> {
+        match tree {
+            Tree::String(_) | Tree::Null | Tree::Bool(_) => Some(tree.clone()),
+            Tree::Format(_, string) => Some(Tree::String(string.clone())),
+            Tree::Variant(typ, ..) if !typ.is_aggregate() => Some(tree.clone()),
+            Tree::Ident(var) if self.is_const(var) => Some(self.const_value(var)),
+            _ => self.eval_const(tree).map(Tree::Number),
+        }
+    }
+
+    fn eval_const(&self, tree: &Tree) -> Option<usize> {
+        match tree {
+            Tree::Number(value) => Some(*value),
+            Tree::Bool(value) => Some(*value as usize),
+            Tree::Ident(var) if self.is_const(var) => match self.const_value(var) {
+                Tree::Number(value) => Some(value),
+                Tree::Bool(value) => Some(value as usize),
+                _ => None,
+            },
+            Tree::BinOp(left, op, right, _) => {
+                let (left, right) = (self.eval_const(left)?, self.eval_const(right)?);
+                match op {
+                    Token::Plus => Some(left.wrapping_add(right)),
+                    Token::Minus => Some(left.wrapping_sub(right)),
+                    Token::Multiply => Some(left.wrapping_mul(right)),
+                    Token::Divide => left.checked_div(right),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn is_const(&self, ident: &String) -> bool {
+        matches!(self.find_var(ident).storage, Storage::Const(_))
+    }
+
+    fn const_value(&self, ident: &String) -> Tree {
+        match &self.find_var(ident).storage {
+            Storage::Const(value) => value.clone(),
+            _ => panic!("{} is not a const", ident),
+        }
+    }
+
+    fn is_readonly(&self, tree: &Tree) -> bool {
+        let global = match tree {
+            Tree::Ident(var) => matches!(
+                self.find_var(var).storage,
+                Storage::ReadOnly(_) | Storage::Const(_)
+            ),
+            _ => false,
+        };
+        global || self.type_of(tree) == Type::Str
+    }
+
+    fn check_mutable(&self, ident: &String) {
+        if matches!(
+            self.find_var(ident).storage,
+            Storage::ReadOnly(_) | Storage::Const(_)
+        ) {
+            panic!("Can't assign to const {}", ident);
+        }
+    }
+
+    // locals first, then the globals of the current module, then qualified names
+    fn find_var(&self, ident: &String) -> &Var {
+        let qualified = self.qualify(ident);
+        self.vars
+            .iter()
+            .find(|var| var.name == *ident)
+            .or_else(|| self.globals.iter().find(|var| var.name == qualified))
+            .or_else(|| self.globals.iter().find(|var| var.name == *ident))
+            .unwrap_or_else(|| panic!("{} not declared", ident))
+    }
+
+    // what the builtins return, args() is the command line and env(name) a variable or ""
+    fn builtin_type(name: &str) -> Option<Type> {
+        match name {
+            "args" => Some(Type::Slice(Box::new(Type::Str))),
+            "env" => Some(Type::Str),
+            "alloc" | "realloc" => Some(Type::Ptr(Box::new(Type::U8))),
+            "ptr_eq" => Some(Type::Bool),
+            "len" | "write" => Some(Type::I64),
+            _ if Self::arith_builtin(name).is_some() => Some(Type::I64),
+            _ => None,
+        }
+    }
+
+    // builtins aren't in here, a fn with the same name shadows them
+    fn find_fn(&self, name: &str) -> Option<&Function> {
+        let qualified = self.qualify(name);
+        self.functions
+            .iter()
+            .find(|function| function.name == qualified)
+            .or_else(|| self.functions.iter().find(|function| function.name == name))
+    }
+
+    fn qualify(&self, name: &str) -> String {
+        match self.module.as_str() {
+            "" => name.to_string(),
+            module => format!("{module}.{name}"),
+        }
+    }
+
+    // `std.io.print` can't be a label
+    fn mangle(name: &str) -> String {
+        name.replace('.', "__")
+    }
+
+    fn declare_fn(&mut self, name: &str, params: &[(String, Type)], ret: &Option<Type>) {
+        if self.functions.iter().any(|function| function.name == name) {
+            panic!("fn {} is already declared", name);
+        }
+        let types: Vec<Type> = params.iter().map(|(_, typ)| typ.clone()).collect();
+        if let Some(typ) = types.iter().chain(ret).find(|typ| typ.is_aggregate()) {
+            panic!("fn {} can't pass {:?} by value, use a pointer", name, typ);
+        }
+        if types.iter().map(Type::slots).sum::<usize>() > ARG_REGS.len() {
+            panic!(
+                "fn {} takes more than {} registers of arguments",
+                name,
+                ARG_REGS.len()
+            );
+        }
+        self.functions.push(Function {
+            name: name.to_string(),
+            params: types,
+            ret: ret.clone(),
+        });
+    }
+
+    // every fn has its own frame below the caller's, its params are spilled into it on entry
+    fn gen_fn(&mut self, name: &str, params: &[(String, Type)], ret: &Option<Type>, body: &[Tree]) {
+        if !self.scopes.is_empty() || self.function.is_some() {
+            panic!("fn {} must be declared at the top level", name);
+        }
+        let end = self.create_lb();
+        let vars = std::mem::take(&mut self.vars);
+        let defers = std::mem::take(&mut self.defers);
+        let (stack, max_stack) = (self.stack, self.max_stack);
+        (self.stack, self.max_stack) = (0, 0);
+        self.function = Some((ret.clone(), end.clone()));
+
+        let mut regs = ARG_REGS.iter();
+        let mut spills = String::new();
+        for (param, typ) in params {
+            let stack_loc = self.alloc_slots(typ.slots());
+            for slot in 0..typ.slots() {
+                let reg = regs.next().unwrap();
+                spills += &format!("\tmov QWORD {}, {reg}\n", self.stack_addr(stack_loc + slot));
+            }
+            let var = Var::new(param.to_string(), Storage::Stack(stack_loc), typ.clone());
+            self.vars.push(var);
+        }
+        // the last expression is returned unless the body ends in a statement
+        let value = match body.last() {
+            Some(
+                Tree::Return(_)
+                | Tree::Exit(_)
+                | Tree::Loop { .. }
+                | Tree::While { .. }
+                | Tree::DoWhile { .. },
+            ) => None,
+            Some(last) if self.diverges(last) => None,
+            _ => ret.as_ref(),
+        };
+        let code = match value {
+            Some(typ) => self.gen_block_value(body, typ),
+            None => self.create_scope(body),
+        };
+
+        // the return address is on the stack, an odd number of slots realigns it
+        let frame = (self.max_stack | 1) * 8;
+        self.functions_section += &format!("{}:\n", Self::fn_label(name));
+        self.functions_section += &format!("\tsub rsp, {frame}\n");
+        self.functions_section += &spills;
+        self.functions_section += &code;
+        self.functions_section += &format!("{end}:\n\tadd rsp, {frame}\n\tret\n");
+
+        self.vars = vars;
+        self.defers = defers;
+        (self.stack, self.max_stack) = (stack, max_stack);
+        self.function = None;
+    }
+
+    // calls to routines of the runtime rather than to a fn
+    fn gen_builtin(&mut self, name: &str, args: &[Tree], loc: &Loc) -> String {
+        let arity = match name {
+            "args" | "unreachable" => 0..=0,
+            "exit" | "print" | "print_int" | "print_hex" | "env" | "panic" | "alloc" | "free"
+            | "len" => 1..=1,
+            "assert" => 1..=2,
+            "realloc" | "ptr_eq" | "write" => 2..=2,
+            _ if Self::arith_builtin(name).is_some() => 2..=2,
+            _ => panic!("fn {} not declared", name),
+        };
+        if !arity.contains(&args.len()) {
+            let expected = match (arity.start(), arity.end()) {
+                (start, end) if start == end => start.to_string(),
+                (start, end) => format!("{start} or {end}"),
+            };
+            panic!(
+                "{} takes {} arguments, found {} at {}:{}",
+                name,
+                expected,
+                args.len(),
+                self.options.file_name,
+                loc
+            );
+        }
+        match (name, args) {
+            // release builds drop asserts, but not before checking them
+            ("assert", [cond, msg @ ..]) if self.options.release => {
+                self.check_condition(cond);
+                msg.iter().for_each(|msg| self.check_types(&Type::Str, msg));
+                return String::new();
+            }
+            ("assert", [cond, msg @ ..]) => {
+                let mut buffer = format!("\t;; assert({:?}) ;;\n", cond);
+                buffer += &self.gen_cmp_exp(cond);
+                buffer += "\ttest al, 1\n";
+                let failed = Tree::String("assertion failed".to_string());
+                buffer += &self.gen_check("jnz", msg.first().unwrap_or(&failed), loc);
+                return buffer;
+            }
+            (_, [left, right]) if Self::arith_builtin(name).is_some() => {
+                for arg in [left, right] {
+                    let typ = self.type_of(arg);
+                    if !typ.is_int() {
+                        panic!(
+                            "{} takes integers, found {:?} at {}:{}",
+                            name, typ, self.options.file_name, loc
+                        );
+                    }
+                }
+                return match Self::arith_builtin(name).unwrap() {
+                    ("wrapping", op) => self.gen_bin_op(left, right, op, "rax", None),
+                    ("saturating", op) => self.gen_saturating(left, right, op, "rax"),
+                    (_, op) => self.gen_bin_op(left, right, op, "rax", Some(loc)),
+                };
+            }
+            ("panic", [msg]) => return self.gen_panic(msg, loc),
+            ("len", [expr]) => {
+                let len = Tree::Field(Box::new(expr.clone()), "len".to_string());
+                return self.gen_expr(&len, "rax");
+            }
+            ("write", [fd, string]) => {
+                let typ = self.type_of(string);
+                if !typ.is_text() {
+                    panic!(
+                        "write takes text, found {:?} at {}:{}",
+                        typ, self.options.file_name, loc
+                    );
+                }
+                let write = Tree::Write(Box::new(fd.clone()), Box::new(string.clone()));
+                let buffer = format!("\t;; write({:?}, {:?}) ;;\n", fd, string);
+                return buffer + &self.gen_expr(&write, "rax");
+            }
+            ("ptr_eq", [a, b]) => {
+                let typ = self.type_of(a);
+                self.check_types(&typ, b);
+                let mut buffer = format!("\t;; ptr_eq({:?}, {:?}) ;;\n", a, b);
+                match typ {
+                    // the same bytes, not just equal ones
+                    _ if typ.is_fat() || matches!(typ, Type::Vec(_)) => {
+                        let tmp = self.alloc_slots(2);
+                        buffer += &self.gen_fat(a);
+                        buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+                        buffer += &format!("\tmov QWORD {}, rdx\n", self.stack_addr(tmp + 1));
+                        buffer += &self.gen_fat(b);
+                        buffer += &format!("\tcmp rax, QWORD {}\n", self.stack_addr(tmp));
+                        buffer += "\tsete al\n";
+                        buffer += &format!("\tcmp rdx, QWORD {}\n", self.stack_addr(tmp + 1));
+                        buffer += "\tsete cl\n";
+                        buffer += "\tand al, cl\n";
+                        self.stack -= 2;
+                    }
+                    Type::Ptr(_) => {
+                        buffer += &self.gen_operands(a, b);
+                        buffer += "\tcmp rax, rbx\n";
+                        buffer += "\tsete al\n";
+                    }
+                    _ => panic!(
+                        "ptr_eq takes strings, slices, Vecs or pointers, found {:?} at {}:{}",
+                        typ, self.options.file_name, loc
+                    ),
+                }
+                buffer += "\tmovzx rax, al\n";
+                return buffer;
+            }
+            ("alloc", [size]) => {
+                self.use_heap();
+                let mut buffer = format!("\t;; alloc({:?}) ;;\n", size);
+                buffer += &self.gen_expr(size, "rdi");
+                buffer += "\tcall __alloc\n";
+                return buffer;
+            }
+            ("realloc", [ptr, size]) => {
+                self.check_ptr(name, ptr, loc);
+                self.use_heap();
+                let mut buffer = format!("\t;; realloc({:?}, {:?}) ;;\n", ptr, size);
+                buffer += &self.gen_operands(ptr, size);
+                buffer += "\tmov rdi, rax\n\tmov rsi, rbx\n";
+                if self.options.debug_alloc {
+                    buffer += &self.gen_location(loc, "rdx", "rcx");
+                }
+                buffer += "\tcall __realloc\n";
+                return buffer;
+            }
+            ("free", [ptr]) => {
+                self.check_ptr(name, ptr, loc);
+                self.use_heap();
+                let mut buffer = format!("\t;; free({:?}) ;;\n", ptr);
+                buffer += &self.gen_expr(ptr, "rdi");
+                if self.options.debug_alloc {
+                    buffer += &self.gen_location(loc, "rsi", "rdx");
+                }
+                buffer += "\tcall __free\n";
+                return buffer;
+            }
+            ("exit", [code]) => {
+                return self.gen_linux_64_program(&Tree::Exit(Box::new(code.clone())))
+            }
+            ("unreachable", _) => {
+                let msg = Tree::String("entered unreachable code".to_string());
+                return self.gen_panic(&msg, loc);
+            }
+            ("args", _) => {
+                self.args = true;
+                return "\tmov rax, QWORD [__args]\n\tmov rdx, QWORD [__args + 8]\n".to_string();
+            }
+            ("env", [var]) => {
+                self.check_types(&Type::Str, var);
+                self.use_runtime(runtime::ENV);
+                let mut buffer = format!("\t;; env({:?}) ;;\n", var);
+                buffer += &self.gen_fat(var);
+                buffer += "\tmov rsi, rax\n";
+                buffer += "\tcall __env\n";
+                return buffer;
+            }
+            _ => (),
+        }
+        let (routine, [arg]) = (name, args) else {
+            unreachable!()
+        };
+        if let ("print", Tree::Format(parts, _)) = (routine, arg) {
+            return self.gen_format(parts);
+        }
+        let typ = self.type_of(arg);
+        if let ("print", Type::Bool) = (routine, &typ) {
+            return self.gen_format(&[Part::Expr(arg.clone(), Spec::default())]);
+        }
+        let mut buffer = format!("\t;; {}({:?}) ;;\n", name, arg);
+        let label = match (routine, &typ) {
+            ("print", Type::Str | Type::Slice(_) | Type::Vec(_))
+                if typ.elem() == Some(Type::U8) =>
+            {
+                let write = Tree::Write(Box::new(Tree::Number(1)), Box::new(arg.clone()));
+                return buffer + &self.gen_expr(&write, "rax");
+            }
+            ("print" | "print_int", _) if typ.is_signed() => "__print_int",
+            ("print" | "print_int", _) if typ.is_int() => "__print_uint",
+            ("print_hex", _) if typ.is_int() || matches!(typ, Type::Ptr(_)) => "__print_hex",
+            _ => panic!(
+                "{} can't print {:?} at {}:{}",
+                name, typ, self.options.file_name, loc
+            ),
+        };
+        match label {
+            "__print_hex" => {
+                self.use_runtime(runtime::HEX_DIGITS);
+                self.use_runtime(runtime::PRINT_HEX);
+            }
+            _ => self.use_runtime(runtime::PRINT_INT),
+        }
+        buffer += &self.gen_expr(arg, "rax");
+        buffer += &format!("\tcall {label}\n");
+        buffer
+    }
+
+    // the element type of a Vec or of a pointer to one
+    fn vec_elem(typ: &Type) -> Option<Type> {
+        match typ {
+            Type::Vec(elem) => Some(*elem.clone()),
+            Type::Ptr(typ) => match &**typ {
+                Type::Vec(elem) => Some(*elem.clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // Vecs are the only types with methods, String is a Vec[u8] with a few more
+    fn gen_method(&mut self, base: &Tree, method: &str, args: &[Tree], loc: &Loc) -> String {
+        let typ = self.type_of(base);
+        let Some(elem) = Self::vec_elem(&typ) else {
+            panic!(
+                "{:?} of type {:?} has no method {} at {}:{}",
+                base, typ, method, self.options.file_name, loc
+            );
+        };
+        let arity = match (method, &elem) {
+            ("push", _) | ("append" | "append_int", Type::U8) => 1,
+            ("pop", _) | ("as_str", Type::U8) => 0,
+            _ => panic!(
+                "{:?} has no method {} at {}:{}",
+                typ, method, self.options.file_name, loc
+            ),
+        };
+        if args.len() != arity {
+            panic!(
+                "{} takes {} arguments, found {} at {}:{}",
+                method,
+                arity,
+                args.len(),
+                self.options.file_name,
+                loc
+            );
+        }
+        let mut buffer = format!("\t;; {:?}.{}({:?}) ;;\n", base, method, args);
+        match (method, args) {
+            ("push", [value]) => buffer += &self.gen_push(base, &elem, value, loc),
+            ("pop", _) => buffer += &self.gen_pop(base, &elem, loc),
+            ("append", [string]) => {
+                self.check_types(&Type::Str, string);
+                let tmp = self.alloc_slots(2);
+                buffer += &self.gen_fat(string);
+                buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+                buffer += &format!("\tmov QWORD {}, rdx\n", self.stack_addr(tmp + 1));
+                buffer += &self.gen_extend(base, tmp, loc);
+                self.stack -= 2;
+            }
+            ("append_int", [value]) => {
+                let typ = self.type_of(value);
+                if !typ.is_int() {
+                    panic!(
+                        "append_int takes an integer, found {:?} at {}:{}",
+                        typ, self.options.file_name, loc
+                    );
+                }
+                self.use_runtime(runtime::HEX_DIGITS);
+                self.use_runtime(runtime::FMT_INT);
+                // the digits are written backwards into 32 bytes of temps, then appended
+                let tmp = self.alloc_slots(6);
+                buffer += &self.gen_expr(value, "rax");
+                buffer += &format!("\tlea rdi, {}\n", self.stack_addr(tmp + 4));
+                buffer += "\tmov ecx, 10\n";
+                buffer += &format!("\tmov r8d, {}\n", typ.is_signed() as usize);
+                buffer += "\txor r9d, r9d\n";
+                buffer += "\tcall __fmt_int\n";
+                buffer += &format!("\tmov QWORD {}, rsi\n", self.stack_addr(tmp + 4));
+                buffer += &format!("\tmov QWORD {}, rdx\n", self.stack_addr(tmp + 5));
+                buffer += &self.gen_extend(base, tmp + 4, loc);
+                self.stack -= 6;
+            }
+            // as_str
+            _ => {
+                buffer += &self.gen_expr(base, "rax");
+                buffer += "\tmov rdx, QWORD [rax + 8]\n";
+                buffer += "\tmov rax, QWORD [rax]\n";
+            }
+        }
+        buffer
+    }
+
+    // leaves the address of the Vec in rdi with room for `count` more elements
+    fn gen_reserve(&mut self, base: &Tree, elem: &Type, count: &str, loc: &Loc) -> String {
+        self.use_heap();
+        self.use_runtime(runtime::PANIC);
+        self.use_runtime(runtime::VEC_RESERVE);
+        let mut buffer = self.gen_expr(base, "rax");
+        buffer += "\tmov rdi, rax\n";
+        buffer += &format!("\tmov rsi, {}\n", elem.size());
+        buffer += &format!("\tmov rdx, {count}\n");
+        buffer += &self.gen_location(loc, "r8", "r9");
+        buffer += "\tcall __vec_reserve\n";
+        buffer
+    }
+
+    fn gen_push(&mut self, base: &Tree, elem: &Type, value: &Tree, loc: &Loc) -> String {
+        self.check_types(elem, value);
+        let slots = if elem.is_fat() { 2 } else { 1 };
+        let tmp = self.alloc_slots(slots);
+        // aggregates are copied from their address
+        let mut buffer = match elem.is_aggregate() {
+            true => self.gen_expr(value, "rax"),
+            false => self.gen_value(value, elem),
+        };
+        buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(tmp));
+        if elem.is_fat() {
+            buffer += &format!("\tmov QWORD {}, rdx\n", self.stack_addr(tmp + 1));
+        }
+        buffer += &self.gen_reserve(base, elem, "1", loc);
+        buffer += "\tmov rbx, QWORD [rdi + 8]\n";
+        if elem.size() != 1 {
+            buffer += &format!("\timul rbx, rbx, {}\n", elem.size());
+        }
+        buffer += "\tadd rbx, QWORD [rdi]\n";
+        buffer += "\tinc QWORD [rdi + 8]\n";
+        buffer += &format!("\tmov rax, QWORD {}\n", self.stack_addr(tmp));
+        if elem.is_fat() {
+            buffer += &format!("\tmov rdx, QWORD {}\n", self.stack_addr(tmp + 1));
+        }
+        match elem.is_aggregate() {
+            true => {
+                buffer += "\tmov rsi, rax\n\tmov rdi, rbx\n";
+                buffer += &format!("\tmov rcx, {}\n", elem.size());
+                buffer += "\trep movsb\n";
+            }
+            false => buffer += &self.store(elem, "rbx"),
+        }
+        self.stack -= slots;
+        buffer
+    }
+
+    // the last element in rax (and rdx), or its address when it's an aggregate
+    fn gen_pop(&mut self, base: &Tree, elem: &Type, loc: &Loc) -> String {
+        let mut buffer = self.gen_expr(base, "rax");
+        buffer += "\tmov rdi, rax\n";
+        buffer += "\tmov rcx, QWORD [rdi + 8]\n";
+        if self.options.bounds_checks {
+            buffer += "\ttest rcx, rcx\n";
+            let msg = Tree::String("pop from an empty Vec".to_string());
+            buffer += &self.gen_check("jnz", &msg, loc);
+        }
+        buffer += "\tdec rcx\n";
+        buffer += "\tmov QWORD [rdi + 8], rcx\n";
+        if elem.size() != 1 {
+            buffer += &format!("\timul rcx, rcx, {}\n", elem.size());
+        }
+        buffer += "\tadd rcx, QWORD [rdi]\n";
+        buffer += &match elem {
+            _ if elem.is_aggregate() => "\tmov rax, rcx\n".to_string(),
+            _ if elem.is_fat() => {
+                "\tmov rax, QWORD [rcx]\n\tmov rdx, QWORD [rcx + 8]\n".to_string()
+            }
+            _ => self.load(elem, "rax", "rcx"),
+        };
+        buffer
+    }
+
+    // appends the bytes of the str in the `tmp` slots to a Vec[u8]
+    fn gen_extend(&mut self, base: &Tree, tmp: usize, loc: &Loc) -> String {
+        let len = format!("QWORD {}", self.stack_addr(tmp + 1));
+        let mut buffer = self.gen_reserve(base, &Type::U8, &len, loc);
+        buffer += "\tmov rax, rdi\n";
+        buffer += "\tmov rdi, QWORD [rax]\n";
+        buffer += "\tadd rdi, QWORD [rax + 8]\n";
+        buffer += &format!("\tmov rsi, QWORD {}\n", self.stack_addr(tmp));
+        buffer += &format!("\tmov rcx, {len}\n");
+        buffer += "\tadd QWORD [rax + 8], rcx\n";
+        buffer += "\trep movsb\n";
+        buffer
+    }
+
+    // falls through to a panic with `msg` unless `jump` is taken
+    fn gen_check(&mut self, jump: &str, msg: &Tree, loc: &Loc) -> String {
+        let ok = self.create_lb();
+        let mut buffer = format!("\t{jump} {ok}\n");
+        buffer += &self.gen_panic(msg, loc);
+        buffer += &format!("{ok}:\n");
+        buffer
+    }
+
+    // `checked_add` is ("checked", "add"), checked ones panic even without --checked-arithmetic
+    fn arith_builtin(name: &str) -> Option<(&str, &str)> {
+        let (mode, op) = name.split_once('_')?;
+        let op = match (mode, op) {
+            ("wrapping" | "saturating" | "checked", "add" | "sub") => op,
+            ("wrapping" | "saturating" | "checked", "mul") => "imul",
+            ("checked", "div") => "div",
+            _ => return None,
+        };
+        Some((mode, op))
+    }
+
+    // `panic at main.kr:12:5: msg` on stderr, then exit(101)
+    fn gen_panic(&mut self, msg: &Tree, loc: &Loc) -> String {
+        self.check_types(&Type::Str, msg);
+        self.use_runtime(runtime::PANIC);
+        let mut buffer = format!("\t;; panic({:?}) ;;\n", msg);
+        buffer += &self.gen_fat(msg);
+        buffer += "\tmov r8, rax\n\tmov r9, rdx\n";
+        buffer += &self.gen_location(loc, "rsi", "rdx");
+        buffer += "\tjmp __panic\n";
+        buffer
+    }
+
+    // the `panic at main.kr:12:5: ` prefix as a str in two registers
+    fn gen_location(&mut self, loc: &Loc, ptr: &str, len: &str) -> String {
+        let location = format!("panic at {}:{}: ", self.options.file_name, loc);
+        let mut buffer = format!("\tmov {len}, {}\n", location.len());
+        buffer += &self.gen_expr(&Tree::String(location), ptr);
+        buffer
+    }
+
+    fn use_heap(&mut self) {
+        if !self.runtime.contains(&runtime::ALLOC) {
+            self.bss_section += "__heap_next: resq 1\n__heap_end: resq 1\n__heap_free: resq 1\n";
+        }
+        self.use_runtime(runtime::ALLOC);
+        self.use_runtime(runtime::REALLOC);
+        match self.options.debug_alloc {
+            true => {
+                self.use_runtime(runtime::PANIC);
+                self.use_runtime(runtime::FREE_DEBUG);
+            }
+            false => self.use_runtime(runtime::FREE),
+        }
+    }
+
+    fn check_ptr(&self, name: &str, ptr: &Tree, loc: &Loc) {
+        if let Tree::Null = ptr {
+            return;
+        }
+        let typ = self.type_of(ptr);
+        if !matches!(typ, Type::Ptr(_)) {
+            panic!(
+                "{} takes a pointer, found {:?} at {}:{}",
+                name, typ, self.options.file_name, loc
+            );
+        }
+    }
+
+    // exit(), panic() and unreachable() never return
+    fn diverges(&self, tree: &Tree) -> bool {
+        match tree {
+            Tree::Exit(_) | Tree::Break(_) | Tree::Return(_) => true,
+            Tree::Call(name, ..) => {
+                matches!(name.as_str(), "exit" | "panic" | "unreachable")
+                    && self.find_fn(name).is_none()
+            }
+            _ => false,
+        }
+    }
+
+    // prints the parts of an interpolated string one after the other
+    fn gen_format(&mut self, parts: &[Part]) -> String {
+        let mut buffer = String::new();
+        for part in parts {
+            let (expr, spec) = match part {
+                Part::Lit(string) => {
+                    let write = Tree::Write(
+                        Box::new(Tree::Number(1)),
+                        Box::new(Tree::String(string.to_string())),
+                    );
+                    buffer += &self.gen_expr(&write, "rax");
+                    continue;
+                }
+                Part::Expr(expr, spec) => (expr, spec),
+                Part::Error(error) => panic!("{}", error),
+            };
+            buffer += &format!("\t;; Format({:?}) ;;\n", expr);
+            let typ = self.type_of(expr);
+            let number = typ.is_int() || matches!(typ, Type::Ptr(_));
+            let mut digits = 0;
+            match &typ {
+                Type::Bool => {
+                    let (yes, no) = (self.intern("true"), self.intern("false"));
+                    let done = self.create_lb();
+                    buffer += &self.gen_expr(expr, "rax");
+                    buffer += &format!("\tmov rsi, str_{}\n\tmov edx, {}\n", yes.loc, yes.len);
+                    buffer += "\ttest al, 1\n";
+                    buffer += &format!("\tjnz {done}\n");
+                    buffer += &format!("\tmov rsi, str_{}\n\tmov edx, {}\n", no.loc, no.len);
+                    buffer += &format!("{done}:\n");
+                }
+                _ if typ.is_fat() && typ.elem() == Some(Type::U8) => {
+                    buffer += &self.gen_fat(expr);
+                    buffer += "\tmov rsi, rax\n";
+                }
+                _ if number => {
+                    self.use_runtime(runtime::HEX_DIGITS);
+                    self.use_runtime(runtime::FMT_INT);
+                    buffer += &self.gen_expr(expr, "rax");
+                    // the digits are written backwards into 32 bytes of temps
+                    digits = 4;
+                    let tmp = self.alloc_slots(digits);
+                    buffer += &format!("\tlea rdi, {}\n", self.stack_addr(tmp + digits));
+                    buffer += &format!("\tmov ecx, {}\n", if spec.hex { 16 } else { 10 });
+                    buffer += &format!("\tmov r8d, {}\n", (typ.is_signed() && !spec.hex) as usize);
+                    buffer += &format!("\tmov r9d, {}\n", if spec.zero { spec.width } else { 0 });
+                    buffer += "\tcall __fmt_int\n";
+                }
+                _ => panic!("Can't format {:?} of type {:?}", expr, typ),
+            }
+            self.use_runtime(runtime::WRITE_PAD);
+            let fill = match spec.fill {
+                Some(fill) => fill,
+                None if spec.zero && !number => '0',
+                None => ' ',
+            };
+            buffer += &format!("\tmov ecx, {}\n", spec.width);
+            buffer += &format!("\tmov r8d, {}\n", fill as u32);
+            buffer += &format!("\tmov r9d, {}\n", spec.left.unwrap_or(!number) as usize);
+            buffer += "\tcall __write_pad\n";
+            self.stack -= digits;
+        }
         buffer
     }
-    fn find_var(&self, ident: &String) -> &Var {
-        self.vars
+
+    fn fn_label(name: &str) -> String {
+        format!("fn_{}", Self::mangle(name))
+    }
+
+    // leaves the result in rax, and rdx for strings and slices
+    fn gen_call(&mut self, name: &str, args: &[Tree], loc: &Loc) -> String {
+        let Some(function) = self.find_fn(name).cloned() else {
+            return self.gen_builtin(name, args, loc);
+        };
+        if args.len() != function.params.len() {
+            panic!(
+                "fn {} takes {} arguments, found {} at {}:{}",
+                function.name,
+                function.params.len(),
+                args.len(),
+                self.options.file_name,
+                loc
+            );
+        }
+        for (typ, arg) in function.params.iter().zip(args) {
+            self.check_types(typ, arg);
+        }
+        let mut buffer = format!("\t;; Call {}({:?}) ;;\n", function.name, args);
+        let simple = function
+            .params
             .iter()
-            .find(|vars| vars.name == ident.to_string())
-            .expect(&format!("{} not declared", ident))
+            .zip(args)
+            .all(|(typ, arg)| !typ.is_fat() && Self::is_simple(arg));
+        if simple {
+            for (arg, reg) in args.iter().zip(ARG_REGS) {
+                buffer += &self.gen_expr(arg, reg);
+            }
+        } else {
+            // other args could clobber the registers, so they all go through the stack
+            let slots: usize = function.params.iter().map(Type::slots).sum();
+            let tmp = self.alloc_slots(slots);
+            let mut slot = tmp;
+            for (typ, arg) in function.params.iter().zip(args) {
+                buffer += &self.gen_value(arg, typ);
+                buffer += &format!("\tmov QWORD {}, rax\n", self.stack_addr(slot));
+                if typ.is_fat() {
+                    buffer += &format!("\tmov QWORD {}, rdx\n", self.stack_addr(slot + 1));
+                }
+                slot += typ.slots();
+            }
+            for (i, reg) in ARG_REGS.iter().take(slots).enumerate() {
+                buffer += &format!("\tmov {reg}, QWORD {}\n", self.stack_addr(tmp + i));
+            }
+            self.stack -= slots;
+        }
+        buffer += &format!("\tcall {}\n", Self::fn_label(&function.name));
+        buffer
     }
 
     fn push(&mut self, buf: &str) -> String {
@@ -422,3 +3240,929 @@ impl Generator {
         format!("\tpop {}\n", buf)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::Module;
+    use crate::parser::{Context, Parser};
+    use crate::tokenize::Tokenizer;
+
+    fn trees(input: &str) -> Vec<Tree> {
+        let (tokens, locs) = Tokenizer::new(input.to_string()).tokenize();
+        Parser::new(&tokens, &locs, Context::default()).parse_tokens()
+    }
+
+    // the top level vars stay declared after generating, so expressions can be typed against them
+    fn generate_with(input: &str, set: impl FnOnce(&mut Options)) -> Generator {
+        let mut options = Options {
+            file_name: "test.kr".to_string(),
+            bounds_checks: true,
+            release: false,
+            checked_arithmetic: false,
+            debug_alloc: false,
+        };
+        set(&mut options);
+        let mut generator = Generator::new(
+            &[Module {
+                name: String::new(),
+                file_name: "test.kr".to_string(),
+                trees: trees(input),
+            }],
+            options,
+        );
+        generator.generate_linux_64();
+        generator
+    }
+
+    fn generate(input: &str) -> Generator {
+        generate_with(input, |_| ())
+    }
+
+    fn type_of(generator: &Generator, expr: &str) -> Type {
+        generator.type_of(&trees(expr)[0])
+    }
+
+    fn ptr(typ: Type) -> Type {
+        Type::Ptr(Box::new(typ))
+    }
+
+    #[test]
+    fn pointer_arithmetic_keeps_the_pointer_type() {
+        let generator = generate("let x = 5\nlet p = &x\nlet q = p + 2\nlet b: u8 = 1\n");
+        assert_eq!(type_of(&generator, "p"), ptr(Type::I64));
+        assert_eq!(type_of(&generator, "*p"), Type::I64);
+        assert_eq!(type_of(&generator, "2 + p"), ptr(Type::I64));
+        assert_eq!(type_of(&generator, "q - p"), Type::I64);
+        assert_eq!(type_of(&generator, "&b"), ptr(Type::U8));
+    }
+
+    #[test]
+    fn null_needs_a_pointer_type() {
+        let generator = generate("let p: *u8 = null\n");
+        assert_eq!(type_of(&generator, "p"), ptr(Type::U8));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't infer the type of null")]
+    fn untyped_null_is_rejected() {
+        generate("let p = null\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't dereference Ident(\"x\") of type I64")]
+    fn only_pointers_are_dereferenced() {
+        generate("let x = 5\nlet y = *x\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid pointer arithmetic")]
+    fn pointers_are_not_multiplied() {
+        generate("let x = 5\nlet p = &x\nlet q = p * 2\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Mismatched types: expected Ptr(U8), found Ptr(I64)")]
+    fn pointee_types_must_match() {
+        generate("let x = 5\nlet p: *u8 = &x\n");
+    }
+
+    #[test]
+    fn arrays_are_indexed_by_element() {
+        let generator = generate("let a: [u8; 4] = [1, 2, 3, 4]\nlet n = [1, 2, 3]\nlet i = 2\n");
+        assert_eq!(type_of(&generator, "a[i]"), Type::U8);
+        assert_eq!(
+            type_of(&generator, "n"),
+            Type::Array(Box::new(Type::I64), 3)
+        );
+        assert_eq!(type_of(&generator, "a + 1"), ptr(Type::U8));
+        assert_eq!(type_of(&generator, "len(a)"), Type::I64);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected 4 elements, found 3")]
+    fn array_literals_fill_the_array() {
+        generate("let a: [u8; 4] = [1, 2, 3]\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't index into Ident(\"x\") of type I64")]
+    fn only_arrays_and_pointers_are_indexed() {
+        generate("let x = 5\nlet y = x[0]\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Ident(\"p\") of type Ptr(I64) has no field len")]
+    fn len_needs_the_array_itself() {
+        generate("let a = [1, 2]\nlet p = a + 0\nlet n = len(p)\n");
+    }
+
+    #[test]
+    fn variable_indexes_are_checked_at_runtime() {
+        let input = "let a: [u8; 4] = [1, 2, 3, 4]\nlet i = 2\nlet b = a[i]\n";
+        assert!(generate(input).runtime.contains(&runtime::BOUNDS_FAIL));
+        let unchecked = generate_with(input, |options| options.bounds_checks = false);
+        assert!(unchecked.runtime.is_empty());
+        assert!(generate("let a = [1, 2]\nlet b = a[1]\n")
+            .runtime
+            .is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "index 4 out of bounds for array of length 4 at test.kr:2:10")]
+    fn constant_indexes_are_checked_at_compile_time() {
+        generate("let a: [u8; 4] = [1, 2, 3, 4]\nlet b = a[4]\n");
+    }
+
+    #[test]
+    fn slicing_keeps_the_kind_of_text() {
+        let generator = generate(
+            "let s = \"hello\"\nlet a = [1, 2, 3]\nlet part: []i64 = a\nlet t: str = s[1..3]\n",
+        );
+        let ints = Type::Slice(Box::new(Type::I64));
+        assert_eq!(type_of(&generator, "s"), Type::Str);
+        assert_eq!(type_of(&generator, "s[1..]"), Type::Str);
+        assert_eq!(type_of(&generator, "s[0]"), Type::U8);
+        assert_eq!(type_of(&generator, "a[..2]"), ints);
+        assert_eq!(type_of(&generator, "part[1..2]"), ints);
+        assert_eq!(type_of(&generator, "s.len"), Type::I64);
+        assert_eq!(type_of(&generator, "s.ptr"), ptr(Type::U8));
+    }
+
+    #[test]
+    #[should_panic(expected = "range 1..5 out of bounds for array of length 3 at test.kr:2:10")]
+    fn constant_ranges_are_checked_at_compile_time() {
+        generate("let a = [1, 2, 3]\nlet b = a[1..5]\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "of type Str has no field size")]
+    fn strs_only_have_a_ptr_and_a_len() {
+        generate("let s = \"hi\"\nlet n = s.size\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Mismatched types: expected Str, found Slice(I64)")]
+    fn only_byte_slices_are_strs() {
+        generate("let a = [1, 2]\nlet b: []i64 = a\nlet s: str = b\n");
+    }
+
+    #[test]
+    fn statics_live_outside_the_stack() {
+        let generator = generate("static buf: [u8; 8]\nbuf[1] = 2\nlet s = \"hi\"\n");
+        assert!(matches!(
+            &generator.find_var(&"buf".to_string()).storage,
+            Storage::Global(label) if label == "static_buf"
+        ));
+        assert!(generator.bss_section.contains("static_buf:\n\tresb 8\n"));
+        // literals are read-only data, not part of any writable section
+        assert!(generator.rodata_section.contains("str_"));
+        assert!(!generator.bss_section.contains("str_"));
+    }
+
+    #[test]
+    #[should_panic(expected = "static buf must be declared at the top level")]
+    fn statics_are_top_level() {
+        generate("if (1 == 1) {\n  static buf: [u8; 8]\n}\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't assign into read-only Ident(\"s\") at test.kr:2:2")]
+    fn strs_are_read_only() {
+        generate("let s = \"hi\"\ns[0] = 1\n");
+    }
+
+    fn storage(generator: &Generator, name: &str) -> Storage {
+        generator.find_var(&name.to_string()).storage.clone()
+    }
+
+    #[test]
+    fn consts_are_folded_into_their_uses() {
+        let generator = generate(
+            "const SIZE = 4 * 2\nconst NEXT = SIZE + 1\nconst MSG = \"hi\"\nconst PRIMES: [u8; 2] = [2, 3]\n",
+        );
+        assert!(matches!(
+            storage(&generator, "SIZE"),
+            Storage::Const(Tree::Number(8))
+        ));
+        assert!(matches!(
+            storage(&generator, "NEXT"),
+            Storage::Const(Tree::Number(9))
+        ));
+        assert!(matches!(
+            storage(&generator, "MSG"),
+            Storage::Const(Tree::String(_))
+        ));
+        assert!(matches!(
+            storage(&generator, "PRIMES"),
+            Storage::ReadOnly(_)
+        ));
+        assert_eq!(generator.eval_const(&trees("SIZE * NEXT - 2")[0]), Some(70));
+        assert_eq!(generator.eval_const(&trees("SIZE / 0")[0]), None);
+    }
+
+    #[test]
+    fn statics_start_with_their_value() {
+        let generator = generate("const SIZE = 8\nstatic counter = SIZE + 2\ncounter++\n");
+        assert!(matches!(storage(&generator, "counter"), Storage::Global(_)));
+        assert!(generator
+            .data_section
+            .contains("static_counter:\n\tdq 10\n"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't assign to const SIZE")]
+    fn consts_cant_be_assigned() {
+        generate("const SIZE = 8\nSIZE = 9\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "const n must be a compile-time constant")]
+    fn consts_must_be_constant() {
+        generate("static a = 1\nconst n = a\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "const B = 300 doesn't fit in U8")]
+    fn consts_must_fit_their_type() {
+        generate("const B: u8 = 300\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "counter must be initialized with a constant")]
+    fn statics_start_constant() {
+        generate("let a = 1\nstatic counter = a\n");
+    }
+
+    #[test]
+    fn fields_have_their_declared_type() {
+        let generator = generate(
+            "struct Point { x: i64, y: u8 }\nlet p = Point { x: 1, y: 2 }\nlet q = &p\nlet w: [i32; 3] = [1, 2, 3]\n",
+        );
+        assert_eq!(type_of(&generator, "p.x"), Type::I64);
+        assert_eq!(type_of(&generator, "p.y"), Type::U8);
+        assert_eq!(type_of(&generator, "q.y"), Type::U8);
+        assert_eq!(type_of(&generator, "w[1]"), Type::I32);
+    }
+
+    #[test]
+    #[should_panic(expected = "struct Point has no field z")]
+    fn unknown_fields_are_rejected() {
+        generate("struct Point { x: i64 }\nlet p = Point { x: 1 }\nlet z = p.z\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "non-exhaustive match at test.kr:3:1, Shape.Dot not covered")]
+    fn matches_must_cover_every_variant() {
+        generate(
+            "enum Shape { Circle(i64), Dot }\nlet s = Shape.Dot\nmatch s {\n  Shape.Circle(r) => s = Shape.Dot\n}\n",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "non-exhaustive match on I64 at test.kr:2:1, add a `_` arm")]
+    fn ints_need_a_wildcard() {
+        generate("let i = 3\nmatch i {\n  0 => i = 1\n}\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Shape.Circle has 1 fields, found 2 bindings")]
+    fn bindings_match_the_payload() {
+        generate(
+            "enum Shape { Circle(i64), Dot }\nlet s = Shape.Dot\nmatch s {\n  Shape.Circle(r, q) => s = Shape.Dot\n  _ => {}\n}\n",
+        );
+    }
+
+    #[test]
+    fn dense_cases_use_a_jump_table() {
+        let mut generator = generate("");
+        let cases = [(0, 1, 10), (1, 2, 11), (2, 4, 12), (5, 6, 13)];
+        let dispatch = generator.gen_dispatch(&cases, 99);
+        // one entry per value from the lowest case to the highest, misses go to the default
+        let table = dispatch.lines().last().unwrap();
+        assert_eq!(table, "\tdq .LB10, .LB11, .LB12, .LB12, .LB99, .LB13");
+        let sparse = generator.gen_dispatch(&[(0, 1, 10), (1000, 1001, 11)], 99);
+        assert!(!sparse.contains("\tdq "));
+    }
+
+    #[test]
+    fn if_and_match_take_the_type_of_their_value() {
+        let generator = generate(
+            "enum Shape { Rect(u8, i64), Dot }\nlet s = Shape.Rect(3, 9)\nlet a = 7\nlet b: u8 = 1\nlet x = if (a > 1) { a } elsif (a == 1) { let z = a * 2; z } els { 0 }\nlet y = if (a > 1) { b } els { b }\nlet m = match s { Shape.Rect(w, h) => w, _ => b }\n",
+        );
+        assert_eq!(type_of(&generator, "x"), Type::I64);
+        assert_eq!(type_of(&generator, "y"), Type::U8);
+        assert_eq!(type_of(&generator, "m"), Type::U8);
+    }
+
+    #[test]
+    #[should_panic(expected = "Mismatched types: expected I64, found Str")]
+    fn branches_must_agree_on_a_type() {
+        generate("let a = 1\nlet x = if (a > 1) { 1 } els { \"s\" }\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "if used as a value needs an els branch")]
+    fn if_values_need_an_els() {
+        generate("let a = 1\nlet x = if (a > 1) { 1 }\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "values can't be produced by blocks yet")]
+    fn block_values_fit_in_registers() {
+        generate("let a = 1\nlet x = if (a > 1) { [1, 2] } els { [3, 4] }\n");
+    }
+
+    // the deferred writes and jumps of a program, in the order they're emitted
+    fn defer_trace(generator: &Generator) -> Vec<&str> {
+        generator
+            .start_section
+            .lines()
+            .filter_map(|line| match line {
+                _ if line.contains("String(\"first") => Some("first"),
+                _ if line.contains("String(\"second") => Some("second"),
+                _ if line.starts_with("\tjmp .LB") => Some("jmp"),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn defers_run_in_reverse_on_every_exit() {
+        let generator = generate(
+            "loop {\n  defer write(1, \"first\")\n  defer write(1, \"second\")\n  break\n}\n",
+        );
+        assert_eq!(
+            defer_trace(&generator),
+            ["second", "first", "jmp", "second", "first", "jmp"]
+        );
+        assert!(generator.defers.is_empty());
+    }
+
+    #[test]
+    fn defers_run_when_their_own_scope_ends() {
+        let generator = generate(
+            "defer write(1, \"first\")\nlet a = 1\nif (a == 1) {\n  defer write(1, \"second\")\n}\n",
+        );
+        assert_eq!(defer_trace(&generator), ["second", "first"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "later not declared")]
+    fn defers_only_see_what_was_declared_before_them() {
+        generate("defer { let x = later }\nlet later = 1\n");
+    }
+
+    #[test]
+    fn calls_take_the_return_type_of_their_fn() {
+        let generator = generate("fn byte(n: i64) -> u8 { return 1 }\n");
+        assert_eq!(type_of(&generator, "byte(2)"), Type::U8);
+    }
+
+    #[test]
+    #[should_panic(expected = "fn one takes 1 arguments, found 2 at test.kr:2:")]
+    fn calls_pass_every_argument() {
+        generate("fn one(n: i64) -> i64 { return n }\nexit(one(1, 2))\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Mismatched types")]
+    fn arguments_match_their_params() {
+        generate("fn one(n: *u8) -> i64 { return 1 }\nexit(one(5))\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "fn f can't pass")]
+    fn structs_are_passed_through_pointers() {
+        generate("struct P { x: i64 }\nfn f(p: P) { }\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "from a fn without a return type")]
+    fn returns_match_their_fn() {
+        generate("fn f() { return 1 }\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "return outside of a fn")]
+    fn return_needs_a_fn() {
+        generate("return\n");
+    }
+
+    #[test]
+    fn prints_pull_in_the_runtime_they_need() {
+        let generator = generate("let n: u8 = 5\nprint(n)\nprint(\"text\")\n");
+        assert!(generator.runtime.contains(&runtime::PRINT_INT));
+        assert!(!generator.runtime.contains(&runtime::PRINT_HEX));
+        let generator = generate("let p: *u8 = null\nprint_hex(p)\n");
+        assert!(generator.runtime.contains(&runtime::PRINT_HEX));
+        assert!(!generator.runtime.contains(&runtime::PRINT_INT));
+    }
+
+    #[test]
+    fn fns_shadow_builtins() {
+        let generator = generate("fn print(n: i64) { }\nprint(5)\n");
+        assert!(generator.runtime.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "print_int can't print Str at test.kr:1:1")]
+    fn print_int_only_prints_ints() {
+        generate("print_int(\"no\")\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "print takes 1 argument")]
+    fn print_takes_one_argument() {
+        generate("print(1, 2)\n");
+    }
+
+    #[test]
+    fn comparisons_are_bools() {
+        let generator = generate("let a = 1\nlet done = a > 2\nlet flag: bool = true\n");
+        assert_eq!(type_of(&generator, "done"), Type::Bool);
+        assert_eq!(type_of(&generator, "flag"), Type::Bool);
+        assert_eq!(type_of(&generator, "a == 1"), Type::Bool);
+    }
+
+    #[test]
+    fn formatting_numbers_pulls_in_the_runtime() {
+        let generator = generate("let n = 5\nlet ok = true\nprint(\"{ok} {n:>4}\")\n");
+        assert!(generator.runtime.contains(&runtime::FMT_INT));
+        let generator = generate("let s = \"text\"\nprint(\"{s:<8}\")\n");
+        assert!(!generator.runtime.contains(&runtime::FMT_INT));
+    }
+
+    #[test]
+    fn braces_outside_print_are_text() {
+        let generator = generate("let n = 5\nlet s = \"{n}\"\nlet t = \"{a b} {n:4b} }\"\n");
+        assert_eq!(type_of(&generator, "s"), Type::Str);
+        assert!(generator.strs.iter().any(|str| str.value == "{n}"));
+        assert!(generator
+            .strs
+            .iter()
+            .any(|str| str.value == "{a b} {n:4b} }"));
+        assert!(!generator.runtime.contains(&runtime::FMT_INT));
+    }
+
+    #[test]
+    fn user_prints_get_the_text() {
+        let generator = generate("let n = 5\nfn print(s: str) { }\nprint(\"{n} {{\")\n");
+        assert!(generator.strs.iter().any(|str| str.value == "{n} {{"));
+        assert!(!generator.runtime.contains(&runtime::FMT_INT));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown format b in {..:4b} at 2:7")]
+    fn print_reports_bad_braces() {
+        generate("let n = 5\nprint(\"{n:4b}\")\n");
+    }
+
+    #[test]
+    fn args_and_env_are_text() {
+        let generator = generate("let argv = args()\nlet home = env(\"HOME\")\n");
+        assert_eq!(
+            type_of(&generator, "argv"),
+            Type::Slice(Box::new(Type::Str))
+        );
+        assert_eq!(type_of(&generator, "home"), Type::Str);
+        assert!(generator.args);
+        assert!(generator.runtime.contains(&runtime::ENV));
+    }
+
+    #[test]
+    fn the_arg_table_is_only_built_when_used() {
+        assert!(!generate("let home = env(\"HOME\")\n").args);
+    }
+
+    #[test]
+    #[should_panic(expected = "Mismatched types: expected Str, found I64")]
+    fn env_takes_a_name() {
+        generate("let home = env(5)\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "args takes 0 arguments, found 1")]
+    fn args_takes_nothing() {
+        generate("let argv = args(1)\n");
+    }
+
+    #[test]
+    fn main_returns_any_int_or_nothing() {
+        let generator = generate("fn main() -> u8 { return 3 }\n");
+        assert_eq!(generator.find_fn("main").unwrap().ret, Some(Type::U8));
+        let generator = generate("fn main() { }\n");
+        assert_eq!(generator.find_fn("main").unwrap().ret, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "fn main can't take parameters")]
+    fn main_takes_nothing() {
+        generate("fn main(a: i64) { }\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "fn main returns the exit code, it can't return Str")]
+    fn main_returns_an_exit_code() {
+        generate("fn main() -> str { return \"x\" }\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only declarations can be at the top level next to fn main")]
+    fn main_owns_the_statements() {
+        generate("let a = 1\nfn main() { }\n");
+    }
+
+    #[test]
+    fn release_drops_asserts() {
+        let input = "let a = 1\nassert(a == 2, \"a is 2\")\n";
+        assert!(generate(input).runtime.contains(&runtime::PANIC));
+        let generator = generate_with(input, |options| options.release = true);
+        assert!(!generator.runtime.contains(&runtime::PANIC));
+    }
+
+    #[test]
+    fn panicking_arms_have_no_value() {
+        let generator = generate(
+            "let n = 1\nlet a = match n { 0 => 10, _ => unreachable() }\nlet b = if (n > 0) { n } els { panic(\"negative\") }\n",
+        );
+        assert_eq!(type_of(&generator, "a"), Type::I64);
+        assert_eq!(type_of(&generator, "b"), Type::I64);
+    }
+
+    #[test]
+    #[should_panic(expected = "assert takes 1 or 2 arguments, found 3 at test.kr:1:1")]
+    fn assert_takes_a_condition_and_a_message() {
+        generate("assert(1 == 1, \"a\", \"b\")\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Mismatched types: expected Str, found I64")]
+    fn panics_take_a_message() {
+        generate("panic(5)\n");
+    }
+
+    #[test]
+    fn checked_arithmetic_panics_on_overflow() {
+        let input = "let a = 1\nlet b = a * 3 + a\n";
+        assert!(!generate(input).runtime.contains(&runtime::PANIC));
+        let checked = generate_with(input, |options| options.checked_arithmetic = true);
+        assert!(checked.runtime.contains(&runtime::PANIC));
+    }
+
+    #[test]
+    fn checked_builtins_always_panic() {
+        let generator =
+            generate("let a = 1\nlet b = checked_add(a, 2)\nlet c = wrapping_mul(a, 3)\n");
+        assert!(generator.runtime.contains(&runtime::PANIC));
+        assert_eq!(type_of(&generator, "c"), Type::I64);
+        let generator =
+            generate("let a = 1\nlet c = wrapping_mul(a, 3)\nlet d = saturating_sub(a, 3)\n");
+        assert!(!generator.runtime.contains(&runtime::PANIC));
+    }
+
+    #[test]
+    fn saturating_clamps_to_the_operand_type() {
+        let asm = asm_with("let a: u8 = 200\nlet b = saturating_add(a, 100)\n", |_| ());
+        assert!(asm.contains("mov rcx, 255\n\tcmp rax, rcx\n\tcmova rax, rcx\n"));
+        let asm = asm_with("let a: i16 = 1\nlet b = saturating_sub(a, 5)\n", |_| ());
+        assert!(asm.contains("mov rcx, 32767\n") && asm.contains("mov rcx, -32768\n"));
+        let asm = asm_with("let a: u64 = 1\nlet b = saturating_sub(a, 5)\n", |_| ());
+        assert!(asm.contains("sub rax, rbx\n\tjnc"));
+        let asm = asm_with("let a = 1\nlet b = saturating_add(a, 5)\n", |_| ());
+        assert!(asm.contains("add rax, rbx\n\tjno"));
+    }
+
+    #[test]
+    #[should_panic(expected = "wrapping_add takes integers, found Str at test.kr:1:9")]
+    fn arithmetic_builtins_take_integers() {
+        generate("let a = wrapping_add(\"x\", 1)\n");
+    }
+
+    #[test]
+    fn the_heap_hands_out_any_pointer() {
+        let generator =
+            generate("let p: *i64 = alloc(16)\np = realloc(p, 32)\nlet q = alloc(8)\nfree(p)\n");
+        assert_eq!(type_of(&generator, "p"), ptr(Type::I64));
+        assert_eq!(type_of(&generator, "q"), ptr(Type::U8));
+        assert!(generator.runtime.contains(&runtime::ALLOC));
+        assert!(generator.runtime.contains(&runtime::FREE));
+        assert!(!generator.runtime.contains(&runtime::PANIC));
+    }
+
+    #[test]
+    fn debug_alloc_catches_double_frees() {
+        let generator = generate_with("let p = alloc(8)\nfree(p)\n", |options| {
+            options.debug_alloc = true
+        });
+        assert!(generator.runtime.contains(&runtime::FREE_DEBUG));
+        assert!(!generator.runtime.contains(&runtime::FREE));
+        assert!(generator.runtime.contains(&runtime::PANIC));
+    }
+
+    #[test]
+    #[should_panic(expected = "free takes a pointer, found I64 at test.kr:2:1")]
+    fn free_takes_a_pointer() {
+        generate("let n = 5\nfree(n)\n");
+    }
+
+    #[test]
+    fn vec_methods_take_the_element_type() {
+        let generator = generate(
+            "let v: Vec[i64]\nlet s: String\nv.push(3)\ns.append(\"hi\")\ns.append_int(v.len)\n",
+        );
+        assert_eq!(type_of(&generator, "v.pop()"), Type::I64);
+        assert_eq!(type_of(&generator, "v.ptr"), ptr(Type::I64));
+        assert_eq!(type_of(&generator, "v.len"), Type::I64);
+        assert_eq!(type_of(&generator, "s.as_str()"), Type::Str);
+        assert_eq!(
+            type_of(&generator, "v[0..1]"),
+            Type::Slice(Box::new(Type::I64))
+        );
+        assert!(generator.runtime.contains(&runtime::VEC_RESERVE));
+    }
+
+    #[test]
+    #[should_panic(expected = "Mismatched types")]
+    fn pushes_match_the_element() {
+        generate("let v: Vec[i64]\nv.push(\"x\")\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Vec(I64) has no method append")]
+    fn only_strings_append_text() {
+        generate("let v: Vec[i64]\nv.append(\"x\")\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Ident(\"n\") of type I64 has no method push")]
+    fn methods_need_a_vec() {
+        generate("let n = 1\nn.push(2)\n");
+    }
+
+    #[test]
+    fn strings_compare_by_content() {
+        let generator = generate("let a = \"x\"\nlet same = a == \"x\"\n");
+        assert!(generator.runtime.contains(&runtime::STR_EQ));
+        assert!(!generator.runtime.contains(&runtime::STR_CMP));
+        let generator = generate("let a = \"x\"\nlet first = a < \"y\"\n");
+        assert!(generator.runtime.contains(&runtime::STR_CMP));
+        assert_eq!(type_of(&generator, "first"), Type::Bool);
+    }
+
+    #[test]
+    fn ptr_eq_compares_identity() {
+        let generator = generate("let a = \"x\"\nlet same = ptr_eq(a, a)\n");
+        assert_eq!(type_of(&generator, "same"), Type::Bool);
+        assert!(!generator.runtime.contains(&runtime::STR_EQ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't compare Vec(U8) with I64")]
+    fn text_only_compares_with_text() {
+        generate("let s: String\nlet same = s == 1\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't compare Str with null")]
+    fn str_is_never_null() {
+        generate("let s = \"a\"\nlet same = s == null\n");
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "ptr_eq takes strings, slices, Vecs or pointers, found I64 at test.kr:1:9"
+    )]
+    fn ptr_eq_needs_something_pointed_at() {
+        generate("let a = ptr_eq(1, 2)\n");
+    }
+
+    #[test]
+    fn concatenation_makes_a_new_str() {
+        let generator = generate("let a = \"x\"\nlet b = a + \"y\"\n");
+        assert_eq!(type_of(&generator, "b"), Type::Str);
+        assert!(generator.runtime.contains(&runtime::ALLOC));
+        assert_eq!(type_of(&generator, "1 + 2"), Type::I64);
+    }
+
+    #[test]
+    fn concatenations_are_freed_by_the_caller() {
+        let generator = generate("let a = \"x\"\nlet b = a + \"y\"\nfree(b.ptr)\n");
+        assert_eq!(type_of(&generator, "b.ptr"), ptr(Type::U8));
+        assert!(generator.runtime.contains(&runtime::FREE));
+        assert!(generator.start_section.contains("call __alloc"));
+        // appending reuses the String's block instead of allocating a new str
+        let generator = generate("let a = \"x\"\nlet s: String\ns.append(a)\ns.append(\"y\")\n");
+        assert!(generator.runtime.contains(&runtime::VEC_RESERVE));
+        assert!(!generator.start_section.contains("call __alloc"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Mismatched types: expected Str, found U8")]
+    fn for_goes_over_the_elements() {
+        generate("for b -> \"ab\" { let s: str = b }\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "c not declared")]
+    fn for_vars_end_with_the_loop() {
+        generate("for c -> 0..3 { }\nexit(c)\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't iterate over Ident(\"n\") of type I64")]
+    fn for_needs_something_to_iterate() {
+        generate("let n = 3\nfor c -> n { }\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "asm can't use rsp at test.kr:1:1")]
+    fn asm_cant_touch_the_stack_pointer() {
+        generate("asm(rsp = 1) { \"nop\" }\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "rax is bound twice in asm")]
+    fn asm_inputs_are_distinct() {
+        generate("asm(rax = 1, rax = 2) { \"nop\" }\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "asm can't write s of type Str")]
+    fn asm_outputs_fit_a_register() {
+        generate("let s = \"x\"\nasm { \"nop\" } -> s = rax\n");
+    }
+
+    #[test]
+    fn literals_are_emitted_as_utf8_bytes() {
+        let str = Strs::new("héllo →".to_string(), 0);
+        assert_eq!(str.len, 10);
+        assert_eq!(
+            str.asciz(),
+            "\"h\", 195, 169, \"llo\", 32, 226, 134, 146, 0"
+        );
+    }
+
+    #[test]
+    fn trailing_letters_are_kept() {
+        let str = Strs::new("a b".to_string(), 0);
+        assert_eq!(str.asciz(), "\"a\", 32, \"b\", 0");
+    }
+
+    #[test]
+    #[should_panic(expected = "exit takes 1 arguments, found 0")]
+    fn exit_takes_the_code() {
+        generate("exit()\n");
+    }
+
+    fn release(input: &str) -> Generator {
+        generate_with(input, |options| options.release = true)
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected a condition")]
+    fn release_still_checks_the_condition() {
+        release("struct P { x: i64 }\nlet p = P { x: 1 }\nassert(p)\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "b not declared")]
+    fn release_still_checks_the_operands() {
+        release("let a = 1\nassert(a == b)\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "expected Str, found I64")]
+    fn release_still_checks_the_message() {
+        release("let a = 1\nassert(a == 1, 5)\n");
+    }
+
+    // which instructions get picked is what these check, so they look at the asm
+    fn asm_with(input: &str, set: impl FnOnce(&mut Options)) -> String {
+        generate_with(input, set).assembly_out
+    }
+
+    fn checked(input: &str) -> String {
+        asm_with(input, |options| options.checked_arithmetic = true)
+    }
+
+    #[test]
+    fn checked_arithmetic_follows_signedness() {
+        let asm = checked("let a = 1\nlet b = a + 2\n");
+        assert!(asm.contains("add rax, rbx\n\tjno"));
+        let asm = checked("let a: u64 = 1\nlet b: u64 = 2\nlet c: u64 = a - b\n");
+        assert!(asm.contains("sub rax, rbx\n\tjnc"));
+        let asm = checked("let a: u64 = 1\nlet b: u64 = a * 3\n");
+        assert!(asm.contains("mul rbx\n\tjno"));
+    }
+
+    #[test]
+    fn narrow_results_must_fit() {
+        let asm = checked("let a: u8 = 1\nlet b: u8 = a + 2\n");
+        assert!(asm.contains("mov rcx, 255\n\tcmp rax, rcx\n\tjbe"));
+        let asm = checked("let a: i16 = 1\nlet b: i16 = a * 2\n");
+        assert!(asm.contains("movsx rcx, ax\n\tcmp rcx, rax\n\tje"));
+    }
+
+    #[test]
+    fn signed_division_uses_idiv() {
+        let asm = asm_with("let a = 0 - 7\nlet b = a / 2\n", |_| ());
+        assert!(asm.contains("cqo\n\tidiv rbx"));
+        let asm = asm_with("let a: u64 = 7\nlet b: u64 = a / 2\n", |_| ());
+        assert!(asm.contains("xor rdx, rdx\n\tdiv rbx"));
+    }
+
+    #[test]
+    fn checked_division_traps_min_by_minus_one() {
+        let asm = checked("let a = 7\nlet b = 0 - 1\nlet c = a / b\n");
+        assert!(asm.contains("cmp rbx, -1"));
+        assert!(asm.contains("\"divide\", 32, \"with\", 32, \"overflow\""));
+    }
+
+    // the `panic at` prefixes a program can report
+    fn panic_locations(generator: &Generator) -> Vec<&str> {
+        generator
+            .strs
+            .iter()
+            .filter(|str| str.value.starts_with("panic at "))
+            .map(|str| str.value.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn growing_a_vec_panics_when_allocation_fails() {
+        let generator = generate("let v: Vec[i64]\nv.push(1)\n");
+        assert!(generator.runtime.contains(&runtime::PANIC));
+        assert!(panic_locations(&generator).contains(&"panic at test.kr:2:2: "));
+    }
+
+    #[test]
+    fn text_compares_by_content() {
+        for (decl, value) in [
+            ("let s = \"ab\"", "s"),
+            ("let s: String", "s"),
+            ("let b: [u8; 2]\nlet s: []u8 = b[..]", "s"),
+        ] {
+            let generator = generate(&format!("{decl}\nlet e = {value} == \"ab\"\n"));
+            assert!(generator.runtime.contains(&runtime::STR_EQ), "{decl}");
+        }
+        let generator = generate("let a = 1\nlet p = &a\nlet e = p == null\n");
+        assert!(!generator.runtime.contains(&runtime::STR_EQ));
+    }
+
+    #[test]
+    fn concatenation_panics_when_allocation_fails() {
+        let generator = generate("let a = \"x\"\nlet b = a + \"y\"\n");
+        assert!(generator.runtime.contains(&runtime::PANIC));
+        assert!(panic_locations(&generator).contains(&"panic at test.kr:2:11: "));
+        assert!(generator
+            .strs
+            .iter()
+            .any(|str| str.value == "allocation failed"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Mismatched types: expected Slice(U8), found Str")]
+    fn literals_dont_become_writable_slices() {
+        generate("let sl: []u8 = \"abc\"\nsl[0] = 65\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Mismatched types: expected Slice(U8), found Str")]
+    fn literals_arent_passed_as_buffers() {
+        generate("fn fill(b: []u8) { b[0] = 1 }\nfill(\"xyz\")\n");
+    }
+
+    #[test]
+    fn len_and_write_are_builtins() {
+        let generator = generate("let s = \"ab\"\nlet n = len(s)\nlet w = write(1, s)\n");
+        assert_eq!(type_of(&generator, "n"), Type::I64);
+        assert_eq!(type_of(&generator, "w"), Type::I64);
+        let generator = generate("fn len(s: str) -> u8 { return 1 }\nlet n = len(\"ab\")\n");
+        assert_eq!(type_of(&generator, "n"), Type::U8);
+    }
+
+    #[test]
+    fn fields_can_use_builtin_names() {
+        let generator =
+            generate("struct B { len: u8, write: i32 }\nlet b = B { len: 1, write: 2 }\n");
+        assert_eq!(type_of(&generator, "b.len"), Type::U8);
+        assert_eq!(type_of(&generator, "b.write"), Type::I32);
+    }
+
+    #[test]
+    #[should_panic(expected = "write takes text, found I64 at test.kr:1:1")]
+    fn write_takes_text() {
+        generate("write(1, 2)\n");
+    }
+
+    #[test]
+    fn unsigned_comparisons_use_unsigned_conditions() {
+        let asm = asm_with("let a: u64 = 5\nlet b: u64 = 3\nlet c = a > b\n", |_| ());
+        assert!(asm.contains("\tcmp rax, rbx\n\tseta al\n"));
+        let asm = asm_with("let a: u8 = 5\nlet c = 3 <= a\n", |_| ());
+        assert!(asm.contains("\tcmp rax, rbx\n\tsetbe al\n"));
+        let asm = asm_with("let a: i32 = 5\nlet c = a < 3\n", |_| ());
+        assert!(asm.contains("\tcmp rax, rbx\n\tsetl al\n"));
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    parser::{Context, Parser, Tree},
+    tokenize::{Token, Tokenizer},
+};
+
+// `import std.io` is compiled into the binary rather than looked up on disk
+static STD: [(&str, &str); 5] = [
+    ("std.io", include_str!("../std/io.kr")),
+    ("std.mem", include_str!("../std/mem.kr")),
+    ("std.str", include_str!("../std/str.kr")),
+    ("std.fmt", include_str!("../std/fmt.kr")),
+    ("std.os", include_str!("../std/os.kr")),
+];
+
+#[derive(Debug, Clone)]
+pub struct Module {
+    // what its top level names are qualified with, empty for the main file
+    pub name: String,
+    pub file_name: String,
+    pub trees: Vec<Tree>,
+}
+
+// parses the main file and everything it imports, each module once, dependencies first
+pub struct Loader {
+    search_paths: Vec<PathBuf>,
+    modules: Vec<Module>,
+    // the chain of imports being loaded, to report cycles
+    loading: Vec<(PathBuf, String)>,
+    context: Context,
+}
+
+impl Loader {
+    pub fn new(search_paths: &[String]) -> Self {
+        Self {
+            search_paths: search_paths.iter().map(PathBuf::from).collect(),
+            modules: vec![],
+            loading: vec![],
+            context: Context::default(),
+        }
+    }
+
+    pub fn load(mut self, file_name: &str) -> Vec<Module> {
+        self.load_module("", file_name);
+        self.modules
+    }
+
+    fn load_module(&mut self, name: &str, file_name: &str) {
+        let path = Self::identity(name, file_name);
+        if let Some(start) = self.loading.iter().position(|(other, _)| *other == path) {
+            let mut chain: Vec<&str> = self.loading[start..]
+                .iter()
+                .map(|(_, file_name)| file_name.as_str())
+                .collect();
+            chain.push(file_name);
+            panic!("Import cycle: {}", chain.join(" -> "));
+        }
+        let loaded = |module: &&Module| Self::identity(&module.name, &module.file_name) == path;
+        if let Some(module) = self.modules.iter().find(loaded) {
+            if module.name != name {
+                panic!(
+                    "{} is imported as both {} and {}",
+                    file_name, module.name, name
+                );
+            }
+            return;
+        }
+        if let Some(module) = self.modules.iter().find(|m| m.name == name) {
+            panic!(
+                "Module {} is both {} and {}",
+                name, module.file_name, file_name
+            );
+        }
+
+        let input = match Self::std_source(name) {
+            Some(source) => source.to_string(),
+            None => fs::read_to_string(file_name)
+                .unwrap_or_else(|_| panic!("Can't open file {file_name}")),
+        };
+        let (tokens, locs) = Tokenizer::new(input).tokenize();
+
+        // imports are loaded before the module itself so their types are known to its parser
+        let imports = self.scan_imports(file_name, &tokens);
+        self.loading.push((path, file_name.to_string()));
+        for (_, module, path) in &imports {
+            self.load_module(module, path);
+        }
+        self.loading.pop();
+
+        let context = Context {
+            module: name.to_string(),
+            imports: imports
+                .into_iter()
+                .map(|(alias, module, _)| (alias, module))
+                .collect(),
+            ..std::mem::take(&mut self.context)
+        };
+        let mut parser = Parser::new(&tokens, &locs, context);
+        let trees = parser.parse_tokens();
+        self.context = parser.context();
+        self.modules.push(Module {
+            name: name.to_string(),
+            file_name: file_name.to_string(),
+            trees,
+        });
+    }
+
+    // alias, module name and file of every `import` in a module
+    fn scan_imports(&self, file_name: &str, tokens: &[Token]) -> Vec<(String, String, String)> {
+        let dir = Path::new(file_name).parent().unwrap_or(Path::new(""));
+        let mut imports = vec![];
+        let mut iter = tokens.iter().peekable();
+        while let Some(token) = iter.next() {
+            if !matches!(token, Token::Import) {
+                continue;
+            }
+            let (module, path) = match iter.next() {
+                // `import "lib/io.kr"` is named after the file
+                Some(Token::String(path)) => {
+                    let module = Path::new(path)
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .unwrap_or_else(|| panic!("Can't import {path}"))
+                        .to_string();
+                    (module, PathBuf::from(path))
+                }
+                // `import a.b` is a/b.kr
+                Some(Token::Ident(first)) => {
+                    let mut parts = vec![first.to_string()];
+                    while let Some(Token::Dot) = iter.peek() {
+                        iter.next();
+                        match iter.next() {
+                            Some(Token::Ident(part)) => parts.push(part.to_string()),
+                            _ => panic!("Expected a module name after . in {file_name}"),
+                        }
+                    }
+                    let path = PathBuf::from(format!("{}.kr", parts.join("/")));
+                    (parts.join("."), path)
+                }
+                _ => panic!("Expected a module path after import in {file_name}"),
+            };
+            let alias = module.rsplit('.').next().unwrap().to_string();
+            if imports.iter().any(|(other, _, _)| *other == alias) {
+                panic!("{} is imported twice in {}", alias, file_name);
+            }
+            let file = match module.strip_prefix("std.") {
+                Some(_) if Self::std_source(&module).is_none() => {
+                    panic!("There's no module {} in the standard library", module)
+                }
+                Some(_) => Some(path.to_string_lossy().to_string()),
+                None => self.resolve(dir, &path),
+            };
+            let file = file.unwrap_or_else(|| {
+                panic!(
+                    "Can't find module {} imported by {} (looked next to it and in {:?})",
+                    module, file_name, self.search_paths
+                )
+            });
+            imports.push((alias, module, file));
+        }
+        imports
+    }
+
+    fn std_source(name: &str) -> Option<&'static str> {
+        STD.iter()
+            .find(|(module, _)| *module == name)
+            .map(|(_, source)| *source)
+    }
+
+    // the standard library is known by name, files by their canonical path
+    fn identity(name: &str, file_name: &str) -> PathBuf {
+        match Self::std_source(name) {
+            Some(_) => PathBuf::from(file_name),
+            None => fs::canonicalize(file_name)
+                .unwrap_or_else(|_| panic!("Can't open file {file_name}")),
+        }
+    }
+
+    // relative to the importing file first, then the search path
+    fn resolve(&self, dir: &Path, path: &Path) -> Option<String> {
+        std::iter::once(dir)
+            .chain(self.search_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .find(|path| path.is_file())
+            .map(|path| path.to_string_lossy().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{Generator, Options};
+
+    // the directory a test writes its modules into, removed even when the test panics
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // writes the files into a fresh directory and loads the first one
+    fn load(test: &str, files: &[(&str, &str)]) -> Vec<Module> {
+        let dir =
+            TempDir(std::env::temp_dir().join(format!("krypton-{}-{test}", std::process::id())));
+        for (name, source) in files {
+            let path = dir.0.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        let main = dir.0.join(files[0].0).to_string_lossy().to_string();
+        Loader::new(&[]).load(&main)
+    }
+
+    #[test]
+    fn imports_are_loaded_first_and_once() {
+        let modules = load(
+            "once",
+            &[
+                (
+                    "main.kr",
+                    "import \"a.kr\"\nimport \"b.kr\"\nexit(a.one() + b.two())\n",
+                ),
+                (
+                    "a.kr",
+                    "import \"b.kr\"\npub fn one() -> i64 { b.two() - 1 }\n",
+                ),
+                ("b.kr", "pub fn two() -> i64 { 2 }\n"),
+            ],
+        );
+        let names: Vec<&str> = modules.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["b", "a", ""]);
+    }
+
+    #[test]
+    fn dotted_imports_are_found_in_directories() {
+        let modules = load(
+            "dotted",
+            &[
+                ("main.kr", "import lib.math\nexit(math.two())\n"),
+                ("lib/math.kr", "pub fn two() -> i64 { 2 }\n"),
+            ],
+        );
+        assert_eq!(modules[0].name, "lib.math");
+        assert!(modules[0].file_name.ends_with("lib/math.kr"));
+    }
+
+    #[test]
+    #[should_panic(expected = "hidden is private to module util")]
+    fn private_names_stay_in_their_module() {
+        load(
+            "private",
+            &[
+                ("main.kr", "import \"util.kr\"\nexit(util.hidden())\n"),
+                ("util.kr", "fn hidden() -> i64 { 1 }\n"),
+            ],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Import cycle: ")]
+    fn cycles_are_reported() {
+        load(
+            "cycle",
+            &[
+                ("main.kr", "import \"a.kr\"\nexit(0)\n"),
+                ("a.kr", "import \"b.kr\"\n"),
+                ("b.kr", "import \"a.kr\"\n"),
+            ],
+        );
+    }
+
+    #[test]
+    fn std_modules_are_built_in() {
+        let modules = load("std", &[("main.kr", "import std.io\nio.println(\"hi\")\n")]);
+        assert!(modules.iter().any(|m| m.name == "std.io"));
+        assert_eq!(modules.last().map(|m| m.name.as_str()), Some(""));
+    }
+
+    #[test]
+    #[should_panic(expected = "There's no module std.nope in the standard library")]
+    fn unknown_std_modules_are_rejected() {
+        load("nope", &[("main.kr", "import std.nope\n")]);
+    }
+
+    #[test]
+    fn the_standard_library_compiles() {
+        let modules = load(
+            "compiles",
+            &[(
+                "main.kr",
+                "import std.io\nimport std.mem\nimport std.str\nimport std.fmt\nimport std.os\nio.println(\"hi\")\n",
+            )],
+        );
+        for name in ["std.io", "std.mem", "std.str", "std.fmt", "std.os"] {
+            assert!(modules.iter().any(|m| m.name == name), "{name} not loaded");
+        }
+        let options = Options {
+            file_name: "main.kr".to_string(),
+            bounds_checks: true,
+            release: false,
+            checked_arithmetic: false,
+            debug_alloc: false,
+        };
+        Generator::new(&modules, options).generate_linux_64();
+    }
+}
@@ -1,43 +1,65 @@
+mod analyzer;
 mod generator;
+mod loader;
 mod parser;
+mod runtime;
 mod tokenize;
-use std::{fs::File, io::Read, io::Write, process::Command};
+mod types;
+use std::{fs::File, io::Write, process::Command};
 
-use generator::Generator;
-use parser::Parser;
-use tokenize::Tokenizer;
+use analyzer::Analyzer;
+use generator::{Generator, Options};
+use loader::Loader;
+
+fn usage() -> ! {
+    eprintln!(
+        "Usages:\n./krypton <file_path> -o <output_path> [-I <dir>] [--no-bounds-checks] [--release] [--checked-arithmetic] [--debug-alloc]"
+    );
+    std::process::exit(1);
+}
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() > 4 {
-        eprintln!("Usages:\n./krypton <file_path> -o <output_path>");
-        std::process::exit(1);
-    }
     let mut file_name = "main.kr";
     let mut output_name = "out";
+    let mut bounds_checks = true;
+    let mut release = false;
+    let mut checked_arithmetic = false;
+    let mut debug_alloc = false;
+    let mut search_paths = vec![];
 
     args.iter()
         .enumerate()
+        .skip(1)
         .for_each(|(i, arg)| match arg.as_str() {
-            "-o" => output_name = &args[i + 1],
+            "-o" => output_name = args.get(i + 1).unwrap_or_else(|| usage()),
+            "-I" => search_paths.push(args.get(i + 1).unwrap_or_else(|| usage()).to_string()),
+            "--no-bounds-checks" => bounds_checks = false,
+            "--release" => release = true,
+            "--checked-arithmetic" => checked_arithmetic = true,
+            "--debug-alloc" => debug_alloc = true,
             _ => {
                 if arg.contains(".kr") {
-                    file_name = &arg
+                    file_name = arg
+                } else if arg.starts_with('-') {
+                    usage()
                 }
             }
         });
 
-    let mut input = String::new();
-    let mut file = File::open(file_name).expect(&format!("Can't open file {file_name}"));
-    file.read_to_string(&mut input).expect("can't read file");
-
-    let tokenizer = Tokenizer::new(input);
-    let tokens = tokenizer.tokenize();
-
-    let mut ast = Parser::new(&tokens);
-    println!("Parse Trees: {:?}", ast.parse_tokens());
+    let modules = Loader::new(&search_paths).load(file_name);
+    for module in &modules {
+        Analyzer::new(&module.file_name).analyze(&module.trees);
+    }
 
-    let mut generator = Generator::new(&ast.parse_tokens());
+    let options = Options {
+        file_name: file_name.to_string(),
+        bounds_checks,
+        release,
+        checked_arithmetic,
+        debug_alloc,
+    };
+    let mut generator = Generator::new(&modules, options);
     let asm_cont = generator.generate_linux_64();
 
     let mut file = File::create(format!("{output_name}.s")).expect("Failed to create file");
@@ -45,7 +67,7 @@ fn main() {
         .expect("Failed to write to file assembly file");
 
     let nasm = Command::new("nasm")
-        .args(&[
+        .args([
             "-f",
             "elf64",
             "-g",
@@ -60,8 +82,10 @@ fn main() {
         .wait();
     if nasm.is_ok() {
         Command::new("ld")
-            .args(&["-o", &output_name, &format!("{output_name}.o")])
+            .args(["-o", output_name, &format!("{output_name}.o")])
             .spawn()
-            .expect("Cannot run ld");
+            .expect("Cannot run ld")
+            .wait()
+            .expect("ld failed");
     }
 }
@@ -1,4 +1,7 @@
-use crate::tokenize::Token;
+use crate::{
+    tokenize::{Loc, Token},
+    types::{Type, Variant},
+};
 
 #[derive(Debug, Clone)]
 pub enum Tree {
@@ -6,13 +9,33 @@ pub enum Tree {
     Ident(String),
     Empty(),
     String(String),
-    BinOp(Box<Tree>, Token, Box<Tree>),
+    Bool(bool),
+    // a string with braces, the builtin print interpolates the parts, anything else gets the text
+    Format(Vec<Part>, String),
+    Null,
+    AddrOf(Box<Tree>),
+    Deref(Box<Tree>),
+    Array(Vec<Tree>),
+    Index(Box<Tree>, Box<Tree>, Loc),
+    Field(Box<Tree>, String),
+    // fields that are left out are zeroed
+    StructLit(Type, Vec<(String, Tree)>),
+    // `Enum.Variant` or `Enum.Variant(payload..)`
+    Variant(Type, String, Vec<Tree>),
+    // what the write builtin and print lower to
+    Write(Box<Tree>, Box<Tree>),
+    // the loc of the operator
+    BinOp(Box<Tree>, Token, Box<Tree>, Loc),
     CmpOp(Box<Tree>, Token, Box<Tree>),
     Inc(String),
     Dec(String),
     Exit(Box<Tree>),
-    Let(String, Box<Tree>),
+    Let(String, Option<Type>, Box<Tree>),
+    // top level globals, statics are mutable and consts are folded at compile time
+    Static(String, Option<Type>, Box<Tree>),
+    Const(String, Option<Type>, Box<Tree>),
     Assign(String, Box<Tree>),
+    Store(Box<Tree>, Box<Tree>),
     If {
         expr: Box<Tree>,
         body: Vec<Tree>,
@@ -33,24 +56,147 @@ pub enum Tree {
         body: Vec<Tree>,
         end: usize,
     },
+    // unconditional, only left through break or exit
+    Loop {
+        start: usize,
+        body: Vec<Tree>,
+        end: usize,
+    },
+    // the condition is checked after the body
+    DoWhile {
+        expr: Box<Tree>,
+        start: usize,
+        body: Vec<Tree>,
+        end: usize,
+    },
+    // jumps to the end label of the innermost loop
+    Break(usize),
+    // runs when the enclosing scope ends, or before an early exit out of it
+    Defer(Vec<Tree>),
+    Fn {
+        name: String,
+        params: Vec<(String, Type)>,
+        ret: Option<Type>,
+        body: Vec<Tree>,
+    },
+    Return(Option<Box<Tree>>),
+    Call(String, Vec<Tree>, Loc),
+    // `v.push(x)`
+    Method(Box<Tree>, String, Vec<Tree>, Loc),
+    // `for i -> a..b` counts, `for c -> s` goes over the elements of a str, slice, array or Vec
     For {
         var: String,
         expr: Box<Tree>,
+        start: usize,
         body: Vec<Tree>,
+        end: usize,
+    },
+    SysCall(Vec<Tree>, Loc),
+    // `asm(rdi = a) [rcx] { "lines" } -> x = rax`, the lines go into the output as they are
+    Asm {
+        lines: Vec<String>,
+        inputs: Vec<(String, Tree)>,
+        clobbers: Vec<String>,
+        // variable and the register it's read from
+        outputs: Vec<(String, String)>,
+        loc: Loc,
+    },
+    Match {
+        expr: Box<Tree>,
+        arms: Vec<Arm>,
+        end: usize,
+        loc: Loc,
     },
-    SysCall(Vec<Tree>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Number(usize),
+    // half open, like slices
+    Range(usize, usize),
+    // enum name, variant name and the names bound to its payload, `_` binds nothing
+    Variant(String, String, Vec<String>),
+    Wildcard,
+}
+
+#[derive(Debug, Clone)]
+pub enum Part {
+    Lit(String),
+    Expr(Tree, Spec),
+    // braces that aren't an interpolation, reported if the string is printed
+    Error(String),
+}
+
+// `{expr:[[fill]<|>][0][width][x|d]}`
+#[derive(Debug, Clone, Default)]
+pub struct Spec {
+    pub fill: Option<char>,
+    // numbers go right and text left unless it's given
+    pub left: Option<bool>,
+    // pads numbers with zeros after the sign
+    pub zero: bool,
+    pub width: usize,
+    pub hex: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Vec<Tree>,
+    pub label: usize,
+    pub loc: Loc,
+}
+
+// what a module is parsed with, and what it hands on to the modules parsed after it
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    // top level names are qualified with it, empty for the main file
+    pub module: String,
+    // alias and module name of every import
+    pub imports: Vec<(String, String)>,
+    pub types: Vec<Type>,
+    // qualified names declared without `pub`
+    pub private: Vec<String>,
+    // labels are shared by all modules
+    pub lb_count: usize,
 }
 
 pub struct Parser {
     tokens: Vec<Token>,
+    locs: Vec<Loc>,
     lb_count: usize,
+    types: Vec<Type>,
+    // labels break jumps to, innermost loop last
+    loops: Vec<usize>,
+    module: String,
+    imports: Vec<(String, String)>,
+    private: Vec<String>,
+    // set by `pub` for the declaration that follows
+    public: bool,
 }
 
 impl Parser {
-    pub fn new(tokens: &Vec<Token>) -> Self {
+    pub fn new(tokens: &[Token], locs: &[Loc], context: Context) -> Self {
         Parser {
             tokens: tokens.to_vec(),
-            lb_count: 0,
+            locs: locs.to_vec(),
+            lb_count: context.lb_count,
+            types: context.types,
+            loops: vec![],
+            module: context.module,
+            imports: context.imports,
+            private: context.private,
+            public: false,
+        }
+    }
+
+    pub fn context(self) -> Context {
+        Context {
+            module: self.module,
+            imports: self.imports,
+            types: self.types,
+            private: self.private,
+            lb_count: self.lb_count,
         }
     }
     pub fn parse_tokens(&mut self) -> Vec<Tree> {
@@ -58,7 +204,11 @@ impl Parser {
         let mut iter = tokens_clone.iter().peekable();
         let mut trees = Vec::new();
 
-        while iter.peek().is_some() {
+        while let Some(token) = iter.peek() {
+            if let Token::Semi = token {
+                iter.next();
+                continue;
+            }
             let tree = self.parse_expression(&mut iter);
             trees.push(tree);
         }
@@ -69,14 +219,23 @@ impl Parser {
         &mut self,
         iter: &mut std::iter::Peekable<std::slice::Iter<Token>>,
     ) -> Tree {
-        let mut left = self.parse_term(iter);
+        let left = self.parse_term(iter);
+        self.parse_expression_from(left, iter)
+    }
 
+    // continues an expression whose left operand was already parsed
+    fn parse_expression_from(
+        &mut self,
+        mut left: Tree,
+        iter: &mut std::iter::Peekable<std::slice::Iter<Token>>,
+    ) -> Tree {
         while let Some(op) = iter.peek().cloned() {
             match op {
                 Token::Plus | Token::Minus => {
+                    let loc = self.loc(iter);
                     iter.next();
                     let right = self.parse_term(iter);
-                    left = Tree::BinOp(Box::new(left), op.clone(), Box::new(right));
+                    left = Tree::BinOp(Box::new(left), op.clone(), Box::new(right), loc);
                 }
                 Token::EquEqu | Token::NotEqu => {
                     iter.next();
@@ -90,7 +249,11 @@ impl Parser {
                 }
                 Token::DDot => {
                     iter.next();
-                    let right = self.parse_expression(iter);
+                    // `s[a..]` runs to the end
+                    let right = match iter.peek() {
+                        Some(Token::CloseBracket) => Tree::Empty(),
+                        _ => self.parse_expression(iter),
+                    };
                     left = Tree::CmpOp(Box::new(left), op.clone(), Box::new(right));
                 }
                 _ => break,
@@ -105,10 +268,13 @@ impl Parser {
 
         while let Some(op) = iter.peek().cloned() {
             match op {
+                // `*p = x` after an expression is the next statement, not `prev * p`
+                Token::Multiply if Self::starts_store(iter.clone()) => break,
                 Token::Multiply | Token::Divide => {
+                    let loc = self.loc(iter);
                     iter.next();
                     let right = self.parse_factor(iter);
-                    left = Tree::BinOp(Box::new(left), op.clone(), Box::new(right));
+                    left = Tree::BinOp(Box::new(left), op.clone(), Box::new(right), loc);
                 }
                 _ => break,
             }
@@ -120,6 +286,7 @@ impl Parser {
         iter: &mut std::iter::Peekable<std::slice::Iter<Token>>,
     ) -> Vec<Tree> {
         let mut body = vec![];
+        let loc = self.loc(iter);
         match iter.peek().unwrap() {
             Token::OpenCurly => {
                 iter.next();
@@ -129,14 +296,15 @@ impl Parser {
                             iter.next();
                             break;
                         }
-                        _ => body.push(self.parse_factor(iter)),
+                        Token::Semi => {
+                            iter.next();
+                        }
+                        // the last expression is the value of the block
+                        _ => body.push(self.parse_expression(iter)),
                     }
                 }
             }
-            _ => {
-                println!("{:?}", iter);
-                panic!("Expected {{")
-            }
+            _ => panic!("Expected {{ at {}", loc),
         }
         body
     }
@@ -177,7 +345,7 @@ impl Parser {
         els: &mut Vec<Tree>,
         els_ifs: &mut Vec<Tree>,
     ) {
-        if !iter.peek().is_none() {
+        if iter.peek().is_some() {
             match iter.peek().unwrap() {
                 Token::Els => {
                     iter.next();
@@ -207,37 +375,90 @@ impl Parser {
         }
     }
     fn parse_factor(&mut self, iter: &mut std::iter::Peekable<std::slice::Iter<Token>>) -> Tree {
+        let loc = self.loc(iter);
         match iter.next().unwrap() {
             Token::Number(num) => Tree::Number(*num),
-            Token::Ident(string) => match iter.peek().unwrap() {
-                Token::Equal => {
-                    iter.next();
-                    let expr = self.parse_expression(iter);
-                    Tree::Assign(string.to_string(), Box::new(expr))
+            Token::Ident(alias) if self.is_import(alias) => {
+                let name = self.parse_qualified(alias, iter);
+                self.parse_ident(name, loc, iter)
+            }
+            Token::Ident(string) => self.parse_ident(string.to_string(), loc, iter),
+            Token::String(string) => Tree::String(Self::unescape(string)),
+            Token::FmtStart(raw) => {
+                let mut parts = vec![];
+                loop {
+                    match iter.next() {
+                        Some(Token::String(string)) => {
+                            parts.push(Part::Lit(Self::unescape(string)))
+                        }
+                        Some(Token::FmtError(error)) => parts.push(Part::Error(error.clone())),
+                        Some(Token::FmtOpen) => {
+                            let expr = self.parse_expression(iter);
+                            let part = match iter.next() {
+                                Some(Token::FmtSpec(spec)) => {
+                                    iter.next();
+                                    match Self::parse_spec(spec, loc) {
+                                        Ok(spec) => Part::Expr(expr, spec),
+                                        Err(error) => Part::Error(error),
+                                    }
+                                }
+                                Some(Token::FmtClose) => Part::Expr(expr, Spec::default()),
+                                _ => {
+                                    while !matches!(iter.next(), Some(Token::FmtClose) | None) {}
+                                    Part::Error(format!(
+                                        "Expected }} after the expression in the string at {}",
+                                        loc
+                                    ))
+                                }
+                            };
+                            parts.push(part);
+                        }
+                        Some(Token::FmtEnd) => break,
+                        _ => panic!("Malformed string interpolation at {}", loc),
+                    }
                 }
-                Token::DPlue => {
-                    iter.next();
-                    Tree::Inc(string.to_string())
+                Tree::Format(parts, Self::unescape(raw))
+            }
+            Token::OpenBracket => {
+                let mut elems = vec![];
+                while let Some(token) = iter.peek() {
+                    match token {
+                        Token::Comma => {
+                            iter.next();
+                        }
+                        Token::CloseBracket => {
+                            iter.next();
+                            break;
+                        }
+                        _ => elems.push(self.parse_expression(iter)),
+                    }
                 }
-                Token::DMinus => {
-                    iter.next();
-                    Tree::Dec(string.to_string())
+                Tree::Array(elems)
+            }
+            Token::Ampersand => {
+                let expr = self.parse_factor(iter);
+                Tree::AddrOf(Box::new(expr))
+            }
+            Token::Multiply => {
+                let target = Tree::Deref(Box::new(self.parse_deref_operand(iter)));
+                match iter.peek() {
+                    Some(Token::Equal) => {
+                        iter.next();
+                        let expr = self.parse_expression(iter);
+                        Tree::Store(Box::new(target), Box::new(expr))
+                    }
+                    _ => target,
                 }
-                _ => Tree::Ident(string.to_string()),
-            },
-            Token::String(string) => Tree::String(
-                // i could use a crate for that  ig if i wanna use unicodes
-                string
-                    .to_string()
-                    .replace("\\n", "\n")
-                    .replace("\\t", "\t")
-                    .replace("\\r", "\r")
-                    .replace("\\\"", "\""),
-            ),
+            }
             Token::Plus => self.parse_factor(iter),
             Token::Minus => {
                 let factor = self.parse_factor(iter);
-                Tree::BinOp(Box::new(Tree::Number(0)), Token::Minus, Box::new(factor))
+                Tree::BinOp(
+                    Box::new(Tree::Number(0)),
+                    Token::Minus,
+                    Box::new(factor),
+                    loc,
+                )
             }
             Token::OpenParen => match iter.peek().unwrap() {
                 Token::CloseParen => {
@@ -253,16 +474,78 @@ impl Parser {
                     }
                 }
             },
-            Token::Let => match iter.next().unwrap() {
-                Token::Ident(var) => match iter.next().unwrap() {
-                    Token::Equal => {
-                        let expr = self.parse_expression(iter);
-                        Tree::Let(var.to_string(), Box::new(expr))
+            Token::Let => {
+                let (var, typ, expr) = self.parse_binding("let", iter);
+                Tree::Let(var, typ, Box::new(expr))
+            }
+            Token::Static => {
+                let (var, typ, expr) = self.parse_binding("static", iter);
+                Tree::Static(self.declare(&var), typ, Box::new(expr))
+            }
+            Token::Const => match self.parse_binding("const", iter) {
+                (_, _, Tree::Empty()) => panic!("Expected '=' after identifier in const statement"),
+                (var, typ, expr) => Tree::Const(self.declare(&var), typ, Box::new(expr)),
+            },
+            Token::Fn => {
+                let name = match (iter.next(), iter.next()) {
+                    (Some(Token::Ident(name)), Some(Token::OpenParen)) => self.declare(name),
+                    _ => panic!("Expected fn <name>(..) at {}", loc),
+                };
+                let mut params: Vec<(String, Type)> = vec![];
+                loop {
+                    match iter.next() {
+                        Some(Token::Comma) => (),
+                        Some(Token::CloseParen) => break,
+                        Some(Token::Ident(param)) => match iter.next() {
+                            Some(Token::Colon) => {
+                                params.push((param.to_string(), self.parse_type(iter)))
+                            }
+                            _ => panic!("Expected `{}: <type>` in fn {}", param, name),
+                        },
+                        _ => panic!("Expected a parameter or ) in fn {}", name),
                     }
-                    _ => panic!("Expected '=' after identifier in let statement"),
-                },
-                _ => panic!("Expected identifier after 'let'"),
+                }
+                let ret = match iter.peek() {
+                    Some(Token::ThinArrow) => {
+                        iter.next();
+                        Some(self.parse_type(iter))
+                    }
+                    _ => None,
+                };
+                let body = self.parse_block(iter);
+                Tree::Fn {
+                    name,
+                    params,
+                    ret,
+                    body,
+                }
+            }
+            Token::Return => match iter.peek() {
+                None | Some(Token::Semi | Token::CloseCurly) => Tree::Return(None),
+                _ => Tree::Return(Some(Box::new(self.parse_expression(iter)))),
             },
+            Token::Pub => {
+                self.public = true;
+                let tree = self.parse_factor(iter);
+                if self.public {
+                    panic!("Expected a declaration after pub at {}", loc);
+                }
+                tree
+            }
+            // the loader already resolved the path, the alias is all the parser needs
+            Token::Import => {
+                match iter.next() {
+                    Some(Token::String(_)) => (),
+                    Some(Token::Ident(_)) => {
+                        while let Some(Token::Dot) = iter.peek() {
+                            iter.next();
+                            iter.next();
+                        }
+                    }
+                    _ => panic!("Expected a module path after import at {}", loc),
+                }
+                Tree::Empty()
+            }
             Token::If => {
                 let mut els = vec![];
                 let mut els_ifs = vec![];
@@ -284,6 +567,10 @@ impl Parser {
             Token::While => {
                 self.craete_lb();
                 let start = self.lb_count;
+                self.craete_lb();
+                let end = self.lb_count;
+                // break leaves through end + 1
+                self.craete_lb();
                 let expr = match iter.next().unwrap() {
                     Token::OpenParen => {
                         let expr = Box::new(self.parse_expression(iter));
@@ -292,10 +579,7 @@ impl Parser {
                     }
                     _ => panic!("Expected ("),
                 };
-                let body = self.parse_block(iter);
-                self.craete_lb();
-                let end = self.lb_count;
-                self.craete_lb();
+                let body = self.parse_loop_body(end + 1, iter);
                 Tree::While {
                     expr,
                     start,
@@ -307,12 +591,19 @@ impl Parser {
                 Token::Ident(var) => match iter.peek().unwrap() {
                     Token::ThinArrow => {
                         iter.next();
+                        self.craete_lb();
+                        let start = self.lb_count;
+                        self.craete_lb();
+                        let end = self.lb_count;
+                        self.craete_lb();
                         let expr = Box::new(self.parse_expression(iter));
-                        let body = self.parse_block(iter);
+                        let body = self.parse_loop_body(end + 1, iter);
                         Tree::For {
                             var: var.to_string(),
                             expr,
+                            start,
                             body,
+                            end,
                         }
                     }
                     _ => panic!("Expected ->"),
@@ -320,13 +611,12 @@ impl Parser {
                 Token::OpenParen => {
                     self.craete_lb();
                     let start = self.lb_count;
-                    let expr = Box::new(self.parse_expression(iter));
-                    iter.next();
-                    self.craete_lb();
-                    let body = self.parse_block(iter);
                     self.craete_lb();
                     let end = self.lb_count;
                     self.craete_lb();
+                    let expr = Box::new(self.parse_expression(iter));
+                    iter.next();
+                    let body = self.parse_loop_body(end + 1, iter);
                     Tree::While {
                         expr,
                         start,
@@ -336,25 +626,1053 @@ impl Parser {
                 }
                 _ => panic!("Expected (Expr) or Var -> (expr..expr)"),
             },
+            Token::Loop => {
+                self.craete_lb();
+                let start = self.lb_count;
+                self.craete_lb();
+                let end = self.lb_count;
+                let body = self.parse_loop_body(end, iter);
+                Tree::Loop { start, body, end }
+            }
+            Token::Do => {
+                self.craete_lb();
+                let start = self.lb_count;
+                self.craete_lb();
+                let end = self.lb_count;
+                let body = self.parse_loop_body(end, iter);
+                let expr = match (iter.next(), iter.next()) {
+                    (Some(Token::While), Some(Token::OpenParen)) => {
+                        let expr = Box::new(self.parse_expression(iter));
+                        iter.next();
+                        expr
+                    }
+                    _ => panic!("Expected while (..) after do {{..}}"),
+                };
+                Tree::DoWhile {
+                    expr,
+                    start,
+                    body,
+                    end,
+                }
+            }
+            Token::Defer => match iter.peek() {
+                Some(Token::OpenCurly) => Tree::Defer(self.parse_block(iter)),
+                _ => Tree::Defer(vec![self.parse_expression(iter)]),
+            },
+            Token::Break => match self.loops.last() {
+                Some(end) => Tree::Break(*end),
+                None => panic!("break outside of a loop at {}", loc),
+            },
             Token::SysCall => match iter.next().unwrap() {
                 Token::OpenParen => {
                     let args = self.parse_args(iter);
                     if args.len() > 7 {
                         panic!("Excessive Args")
                     }
-                    Tree::SysCall(args)
+                    Tree::SysCall(args, loc)
                 }
                 _ => panic!("Expected (..)"),
             },
-            Token::Exit => {
-                let expr = self.parse_factor(iter);
-                Tree::Exit(Box::new(expr))
+            Token::Asm => self.parse_asm(loc, iter),
+            // declarations only exist in the parser, types carry their fields
+            Token::Struct => {
+                let name = match (iter.next(), iter.next()) {
+                    (Some(Token::Ident(name)), Some(Token::OpenCurly)) => self.declare(name),
+                    _ => panic!("Expected struct <name> {{"),
+                };
+                if self.find_type(&name).is_some() {
+                    panic!("{} is already declared", name);
+                }
+                let mut fields: Vec<(String, Type)> = vec![];
+                loop {
+                    match iter.next() {
+                        Some(Token::Comma) => (),
+                        Some(Token::CloseCurly) => break,
+                        Some(Token::Ident(field)) => {
+                            if fields.iter().any(|(other, _)| other == field) {
+                                panic!("Duplicate field {} in struct {}", field, name);
+                            }
+                            match iter.next() {
+                                Some(Token::Colon) => {
+                                    fields.push((field.to_string(), self.parse_type(iter)))
+                                }
+                                _ => panic!("Expected `{}: <type>` in struct {}", field, name),
+                            }
+                        }
+                        _ => panic!("Expected a field or }} in struct {}", name),
+                    }
+                }
+                self.types.push(Type::Struct { name, fields });
+                Tree::Empty()
+            }
+            Token::Enum => {
+                let name = match (iter.next(), iter.next()) {
+                    (Some(Token::Ident(name)), Some(Token::OpenCurly)) => self.declare(name),
+                    _ => panic!("Expected enum <name> {{"),
+                };
+                if self.find_type(&name).is_some() {
+                    panic!("{} is already declared", name);
+                }
+                let mut variants: Vec<Variant> = vec![];
+                loop {
+                    match iter.next() {
+                        Some(Token::Comma | Token::Semi) => (),
+                        Some(Token::CloseCurly) => break,
+                        Some(Token::Ident(variant)) => {
+                            if variants.iter().any(|other| other.name == *variant) {
+                                panic!("Duplicate variant {} in enum {}", variant, name);
+                            }
+                            // tags count up from the previous one, like C
+                            let mut tag = variants.last().map_or(0, |last| last.tag + 1);
+                            let mut fields = vec![];
+                            match iter.peek() {
+                                Some(Token::OpenParen) => {
+                                    iter.next();
+                                    while !matches!(iter.peek(), Some(Token::CloseParen)) {
+                                        fields.push(self.parse_type(iter));
+                                        if let Some(Token::Comma) = iter.peek() {
+                                            iter.next();
+                                        }
+                                    }
+                                    iter.next();
+                                }
+                                Some(Token::Equal) => {
+                                    iter.next();
+                                    tag = match iter.next() {
+                                        Some(Token::Number(tag)) => *tag,
+                                        _ => panic!("Expected a number after {} =", variant),
+                                    };
+                                }
+                                _ => (),
+                            }
+                            if variants.iter().any(|other| other.tag == tag) {
+                                panic!("Duplicate tag {} for {}.{}", tag, name, variant);
+                            }
+                            variants.push(Variant {
+                                name: variant.to_string(),
+                                tag,
+                                fields,
+                            });
+                        }
+                        _ => panic!("Expected a variant or }} in enum {}", name),
+                    }
+                }
+                self.types.push(Type::Enum { name, variants });
+                Tree::Empty()
+            }
+            Token::Match => {
+                let expr = Box::new(self.parse_expression(iter));
+                match iter.next() {
+                    Some(Token::OpenCurly) => (),
+                    _ => panic!("Expected {{ after match expression"),
+                }
+                let mut arms = vec![];
+                loop {
+                    let loc = self.loc(iter);
+                    match iter.peek() {
+                        Some(Token::Comma | Token::Semi) => {
+                            iter.next();
+                            continue;
+                        }
+                        Some(Token::CloseCurly) => {
+                            iter.next();
+                            break;
+                        }
+                        _ => (),
+                    }
+                    let pattern = self.parse_pattern(iter);
+                    match iter.next() {
+                        Some(Token::FatArrow) => (),
+                        _ => panic!("Expected => after pattern"),
+                    }
+                    let body = match iter.peek() {
+                        Some(Token::OpenCurly) => self.parse_block(iter),
+                        _ => vec![self.parse_expression(iter)],
+                    };
+                    self.craete_lb();
+                    arms.push(Arm {
+                        pattern,
+                        body,
+                        label: self.lb_count,
+                        loc,
+                    });
+                }
+                self.craete_lb();
+                Tree::Match {
+                    expr,
+                    arms,
+                    end: self.lb_count,
+                    loc,
+                }
             }
             Token::Els | Token::ElsIf => panic!("Expected If statement first"),
             _ => panic!("Invalid factor"),
         }
     }
+    // a name, possibly qualified, and whatever follows it
+    fn parse_ident(
+        &mut self,
+        string: String,
+        loc: Loc,
+        iter: &mut std::iter::Peekable<std::slice::Iter<Token>>,
+    ) -> Tree {
+        match iter.peek().cloned().unwrap_or(&Token::Semi) {
+            Token::Equal => {
+                iter.next();
+                let expr = self.parse_expression(iter);
+                Tree::Assign(string, Box::new(expr))
+            }
+            Token::DPlue => {
+                iter.next();
+                Tree::Inc(string)
+            }
+            Token::DMinus => {
+                iter.next();
+                Tree::Dec(string)
+            }
+            Token::Dot if matches!(self.find_type(&string), Some(Type::Enum { .. })) => {
+                let typ = self.find_type(&string).unwrap();
+                iter.next();
+                let variant = match iter.next() {
+                    Some(Token::Ident(variant)) => variant.to_string(),
+                    _ => panic!("Expected a variant after {}.", string),
+                };
+                let args = match iter.peek() {
+                    Some(Token::OpenParen) => {
+                        iter.next();
+                        self.parse_args(iter)
+                    }
+                    _ => vec![],
+                };
+                Tree::Variant(typ, variant, args)
+            }
+            Token::OpenCurly if matches!(self.find_type(&string), Some(Type::Struct { .. })) => {
+                let typ = self.find_type(&string).unwrap();
+                iter.next();
+                let mut fields = vec![];
+                loop {
+                    match (iter.next(), iter.next()) {
+                        (Some(Token::CloseCurly), _) => break,
+                        (Some(Token::Ident(field)), Some(Token::Colon)) => {
+                            fields.push((field.to_string(), self.parse_expression(iter)))
+                        }
+                        _ => panic!("Expected `field: value` in {} literal", string),
+                    }
+                    match iter.next() {
+                        Some(Token::Comma) => (),
+                        Some(Token::CloseCurly) => break,
+                        _ => panic!("Expected , or }} in {} literal", string),
+                    }
+                }
+                Tree::StructLit(typ, fields)
+            }
+            Token::OpenBracket | Token::Dot => {
+                let target = self.parse_postfix(Tree::Ident(string), iter);
+                match iter.peek() {
+                    Some(Token::Equal) => {
+                        iter.next();
+                        let expr = self.parse_expression(iter);
+                        Tree::Store(Box::new(target), Box::new(expr))
+                    }
+                    _ => target,
+                }
+            }
+            Token::OpenParen => {
+                iter.next();
+                let call = Tree::Call(string, self.parse_args(iter), loc);
+                self.parse_postfix(call, iter)
+            }
+            // the constants are names rather than keywords, like the builtin types
+            _ => match string.as_str() {
+                "null" => Tree::Null,
+                "true" => Tree::Bool(true),
+                "false" => Tree::Bool(false),
+                _ => Tree::Ident(string),
+            },
+        }
+    }
+    fn unescape(string: &str) -> String {
+        // i could use a crate for that  ig if i wanna use unicodes
+        string
+            .to_string()
+            .replace("\\n", "\n")
+            .replace("\\t", "\t")
+            .replace("\\r", "\r")
+            .replace("\\\"", "\"")
+    }
+
+    fn parse_spec(spec: &str, loc: Loc) -> Result<Spec, String> {
+        let mut result = Spec::default();
+        let mut chars = spec.chars().peekable();
+        let align = |c: char| match c {
+            '<' => Some(true),
+            '>' => Some(false),
+            _ => None,
+        };
+        // a fill char is only one when an alignment follows it
+        let mut ahead = spec.chars();
+        match (ahead.next(), ahead.next()) {
+            (Some(fill), Some(c)) if align(c).is_some() => {
+                result.fill = Some(fill);
+                result.left = align(c);
+                chars.nth(1);
+            }
+            (Some(c), _) if align(c).is_some() => {
+                result.left = align(c);
+                chars.next();
+            }
+            _ => (),
+        }
+        if chars.peek() == Some(&'0') {
+            result.zero = true;
+            chars.next();
+        }
+        while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+            result.width = result.width * 10 + digit as usize;
+            chars.next();
+        }
+        match chars.next() {
+            None | Some('d') => (),
+            Some('x') => result.hex = true,
+            Some(c) => {
+                return Err(format!(
+                    "Unknown format {} in {{..:{}}} at {}",
+                    c, spec, loc
+                ))
+            }
+        }
+        if chars.next().is_some() {
+            return Err(format!("Invalid format spec {} at {}", spec, loc));
+        }
+        Ok(result)
+    }
+
+    // `*p =`, `**pp =` or `*(p + 1) =` ahead
+    fn starts_store(mut iter: std::iter::Peekable<std::slice::Iter<Token>>) -> bool {
+        while let Some(Token::Multiply) = iter.peek() {
+            iter.next();
+        }
+        match iter.next() {
+            Some(Token::Ident(_)) => (),
+            Some(Token::OpenParen) => {
+                let mut depth = 1;
+                while depth > 0 {
+                    match iter.next() {
+                        Some(Token::OpenParen) => depth += 1,
+                        Some(Token::CloseParen) => depth -= 1,
+                        Some(_) => (),
+                        None => return false,
+                    }
+                }
+            }
+            _ => return false,
+        }
+        matches!(iter.next(), Some(Token::Equal))
+    }
+
+    fn parse_deref_operand(
+        &mut self,
+        iter: &mut std::iter::Peekable<std::slice::Iter<Token>>,
+    ) -> Tree {
+        // `*p = x` must not parse as `*(p = x)`, so plain identifiers are taken as is
+        match iter.peek().unwrap() {
+            Token::Ident(var) => {
+                iter.next();
+                Tree::Ident(var.to_string())
+            }
+            Token::Multiply => {
+                iter.next();
+                Tree::Deref(Box::new(self.parse_deref_operand(iter)))
+            }
+            _ => self.parse_factor(iter),
+        }
+    }
+
+    fn parse_postfix(
+        &mut self,
+        mut expr: Tree,
+        iter: &mut std::iter::Peekable<std::slice::Iter<Token>>,
+    ) -> Tree {
+        loop {
+            let loc = self.loc(iter);
+            match iter.peek() {
+                Some(Token::OpenBracket) => {
+                    iter.next();
+                    let index = match iter.peek() {
+                        // `s[..b]` starts at 0
+                        Some(Token::DDot) => self.parse_expression_from(Tree::Number(0), iter),
+                        _ => self.parse_expression(iter),
+                    };
+                    match iter.next() {
+                        Some(Token::CloseBracket) => {
+                            expr = Tree::Index(Box::new(expr), Box::new(index), loc)
+                        }
+                        _ => panic!("Expected ]"),
+                    }
+                }
+                Some(Token::Dot) => {
+                    iter.next();
+                    match iter.next() {
+                        Some(Token::Ident(method))
+                            if matches!(iter.peek(), Some(Token::OpenParen)) =>
+                        {
+                            iter.next();
+                            let args = self.parse_args(iter);
+                            expr = Tree::Method(Box::new(expr), method.to_string(), args, loc)
+                        }
+                        Some(Token::Ident(field)) => {
+                            expr = Tree::Field(Box::new(expr), field.to_string())
+                        }
+                        _ => panic!("Expected field name after ."),
+                    }
+                }
+                _ => break,
+            }
+        }
+        expr
+    }
+
+    // location of the next token
+    fn loc(&self, iter: &std::iter::Peekable<std::slice::Iter<Token>>) -> Loc {
+        let pos = self.tokens.len() - iter.len();
+        self.locs.get(pos).or(self.locs.last()).copied().unwrap()
+    }
+
+    // `<name>[: <type>] [= <expr>]` after let, static and const
+    fn parse_binding(
+        &mut self,
+        keyword: &str,
+        iter: &mut std::iter::Peekable<std::slice::Iter<Token>>,
+    ) -> (String, Option<Type>, Tree) {
+        let var = match iter.next() {
+            Some(Token::Ident(var)) => var.to_string(),
+            _ => panic!("Expected identifier after '{keyword}'"),
+        };
+        let typ = match iter.peek() {
+            Some(Token::Colon) => {
+                iter.next();
+                Some(self.parse_type(iter))
+            }
+            _ => None,
+        };
+        match iter.peek() {
+            Some(Token::Equal) => {
+                iter.next();
+                let expr = self.parse_expression(iter);
+                (var, typ, expr)
+            }
+            // `let buf: [u8; 64]` is zero initialized
+            _ if typ.is_some() => (var, typ, Tree::Empty()),
+            _ => panic!("Expected '=' after identifier in {keyword} statement"),
+        }
+    }
+
+    fn parse_type(&mut self, iter: &mut std::iter::Peekable<std::slice::Iter<Token>>) -> Type {
+        match iter.next().unwrap() {
+            Token::Multiply => Type::Ptr(Box::new(self.parse_type(iter))),
+            Token::OpenBracket if matches!(iter.peek(), Some(Token::CloseBracket)) => {
+                iter.next();
+                Type::Slice(Box::new(self.parse_type(iter)))
+            }
+            Token::OpenBracket => {
+                let typ = self.parse_type(iter);
+                match (iter.next(), iter.next(), iter.next()) {
+                    (Some(Token::Semi), Some(Token::Number(len)), Some(Token::CloseBracket)) => {
+                        Type::Array(Box::new(typ), *len)
+                    }
+                    _ => panic!("Expected [type; length]"),
+                }
+            }
+            Token::Ident(name) => match name.as_str() {
+                "i8" => Type::I8,
+                "i16" => Type::I16,
+                "i32" => Type::I32,
+                "i64" => Type::I64,
+                "u8" => Type::U8,
+                "u16" => Type::U16,
+                "u32" => Type::U32,
+                "u64" => Type::U64,
+                "str" => Type::Str,
+                "bool" => Type::Bool,
+                "String" => Type::Vec(Box::new(Type::U8)),
+                "Vec" => {
+                    let typ = match iter.next() {
+                        Some(Token::OpenBracket) => self.parse_type(iter),
+                        _ => panic!("Expected Vec[type]"),
+                    };
+                    match iter.next() {
+                        Some(Token::CloseBracket) => Type::Vec(Box::new(typ)),
+                        _ => panic!("Expected ] after Vec[{:?}", typ),
+                    }
+                }
+                _ if self.is_import(name) => {
+                    let name = self.parse_qualified(name, iter);
+                    self.find_type(&name)
+                        .unwrap_or_else(|| panic!("Unknown type {}", name))
+                }
+                _ => self
+                    .find_type(name)
+                    .unwrap_or_else(|| panic!("Unknown type {}", name)),
+            },
+            _ => panic!("Expected type"),
+        }
+    }
+
+    fn parse_pattern(
+        &mut self,
+        iter: &mut std::iter::Peekable<std::slice::Iter<Token>>,
+    ) -> Pattern {
+        match iter.next() {
+            Some(Token::Number(start)) => match iter.peek() {
+                Some(Token::DDot) => {
+                    iter.next();
+                    match iter.next() {
+                        Some(Token::Number(end)) => Pattern::Range(*start, *end),
+                        _ => panic!("Expected the end of the range pattern"),
+                    }
+                }
+                _ => Pattern::Number(*start),
+            },
+            Some(Token::Ident(name)) if name == "_" => Pattern::Wildcard,
+            Some(Token::Ident(name)) => {
+                let name = match self.is_import(name) {
+                    true => self.parse_qualified(name, iter),
+                    false => name.to_string(),
+                };
+                let variant = match (iter.next(), iter.next()) {
+                    (Some(Token::Dot), Some(Token::Ident(variant))) => variant.to_string(),
+                    _ => panic!("Expected {}.<variant> pattern", name),
+                };
+                let mut bindings = vec![];
+                if let Some(Token::OpenParen) = iter.peek() {
+                    iter.next();
+                    loop {
+                        match iter.next() {
+                            Some(Token::Ident(binding)) => bindings.push(binding.to_string()),
+                            Some(Token::Comma) => (),
+                            Some(Token::CloseParen) => break,
+                            _ => panic!("Expected names to bind in {}.{}(..)", name, variant),
+                        }
+                    }
+                }
+                Pattern::Variant(name.to_string(), variant, bindings)
+            }
+            _ => panic!("Expected a pattern"),
+        }
+    }
+
+    // types of the module being parsed shadow the qualified names of other modules
+    fn find_type(&self, name: &str) -> Option<Type> {
+        let find = |name: &str| {
+            self.types
+                .iter()
+                .find(|typ| match typ {
+                    Type::Struct { name: other, .. } | Type::Enum { name: other, .. } => {
+                        other == name
+                    }
+                    _ => false,
+                })
+                .cloned()
+        };
+        find(&self.qualify(name)).or_else(|| find(name))
+    }
+
+    fn qualify(&self, name: &str) -> String {
+        match self.module.as_str() {
+            "" => name.to_string(),
+            module => format!("{module}.{name}"),
+        }
+    }
+
+    // the qualified name of a top level declaration, private unless it follows `pub`
+    fn declare(&mut self, name: &str) -> String {
+        let name = self.qualify(name);
+        if !std::mem::take(&mut self.public) {
+            self.private.push(name.clone());
+        }
+        name
+    }
+
+    fn is_import(&self, alias: &str) -> bool {
+        self.imports.iter().any(|(other, _)| other == alias)
+    }
+
+    // `alias.name` into the qualified name of something another module made public
+    fn parse_qualified(
+        &mut self,
+        alias: &str,
+        iter: &mut std::iter::Peekable<std::slice::Iter<Token>>,
+    ) -> String {
+        let (_, module) = self
+            .imports
+            .iter()
+            .find(|(other, _)| other == alias)
+            .unwrap();
+        let name = match (iter.next(), iter.next()) {
+            (Some(Token::Dot), Some(Token::Ident(name))) => format!("{module}.{name}"),
+            _ => panic!("Expected {}.<name>", alias),
+        };
+        if self.private.contains(&name) {
+            panic!("{} is private to module {}", name, module);
+        }
+        name
+    }
+
+    fn parse_asm(
+        &mut self,
+        loc: Loc,
+        iter: &mut std::iter::Peekable<std::slice::Iter<Token>>,
+    ) -> Tree {
+        let mut inputs = vec![];
+        if let Some(Token::OpenParen) = iter.peek() {
+            iter.next();
+            loop {
+                match iter.next() {
+                    Some(Token::CloseParen) => break,
+                    Some(Token::Comma) => (),
+                    Some(Token::Ident(reg)) if matches!(iter.peek(), Some(Token::Equal)) => {
+                        iter.next();
+                        inputs.push((reg.to_string(), self.parse_expression(iter)));
+                    }
+                    _ => panic!("Expected `<register> = <expr>` in asm at {}", loc),
+                }
+            }
+        }
+        let mut clobbers = vec![];
+        if let Some(Token::OpenBracket) = iter.peek() {
+            iter.next();
+            loop {
+                match iter.next() {
+                    Some(Token::CloseBracket) => break,
+                    Some(Token::Comma) => (),
+                    Some(Token::Ident(reg)) => clobbers.push(reg.to_string()),
+                    _ => panic!("Expected a register in the clobbers of asm at {}", loc),
+                }
+            }
+        }
+        let mut lines = vec![];
+        match iter.next() {
+            Some(Token::OpenCurly) => loop {
+                match iter.next() {
+                    Some(Token::CloseCurly) => break,
+                    Some(Token::String(line)) => lines.push(line.to_string()),
+                    _ => panic!("Expected the lines of asm at {} as strings", loc),
+                }
+            },
+            _ => panic!("Expected {{ after asm at {}", loc),
+        }
+        // `-> x` is short for `-> x = rax`
+        let mut outputs = vec![];
+        if let Some(Token::ThinArrow) = iter.peek() {
+            iter.next();
+            loop {
+                let var = match iter.next() {
+                    Some(Token::Ident(var)) => var.to_string(),
+                    _ => panic!("Expected a variable after -> in asm at {}", loc),
+                };
+                if !matches!(iter.peek(), Some(Token::Equal)) {
+                    outputs.push((var, "rax".to_string()));
+                    break;
+                }
+                iter.next();
+                match iter.next() {
+                    Some(Token::Ident(reg)) => outputs.push((var, reg.to_string())),
+                    _ => panic!("Expected a register after {} = in asm at {}", var, loc),
+                }
+                match iter.peek() {
+                    Some(Token::Comma) => iter.next(),
+                    _ => break,
+                };
+            }
+        }
+        Tree::Asm {
+            lines,
+            inputs,
+            clobbers,
+            outputs,
+            loc,
+        }
+    }
+
+    fn parse_loop_body(
+        &mut self,
+        end: usize,
+        iter: &mut std::iter::Peekable<std::slice::Iter<Token>>,
+    ) -> Vec<Tree> {
+        self.loops.push(end);
+        let body = self.parse_block(iter);
+        self.loops.pop();
+        body
+    }
+
     fn craete_lb(&mut self) {
         self.lb_count += 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenize::Tokenizer;
+
+    fn parse(input: &str) -> Vec<Tree> {
+        let (tokens, locs) = Tokenizer::new(input.to_string()).tokenize();
+        Parser::new(&tokens, &locs, Context::default()).parse_tokens()
+    }
+
+    #[test]
+    fn pointer_types_and_address_of() {
+        let trees = parse("let p: *i64 = &x\n");
+        let [Tree::Let(name, Some(typ), expr)] = &trees[..] else {
+            panic!("{:?}", trees);
+        };
+        assert_eq!(name, "p");
+        assert_eq!(*typ, Type::Ptr(Box::new(Type::I64)));
+        assert!(matches!(&**expr, Tree::AddrOf(inner) if matches!(**inner, Tree::Ident(_))));
+    }
+
+    #[test]
+    fn stores_go_through_the_pointer() {
+        let trees = parse("*p = 5\n*(p + 1) = 6\n");
+        let [Tree::Store(first, _), Tree::Store(second, _)] = &trees[..] else {
+            panic!("{:?}", trees);
+        };
+        assert!(matches!(&**first, Tree::Deref(p) if matches!(**p, Tree::Ident(_))));
+        assert!(matches!(&**second, Tree::Deref(p) if matches!(**p, Tree::BinOp(..))));
+    }
+
+    #[test]
+    fn typed_lets_without_a_value_are_zeroed() {
+        let trees = parse("let buf: [u8; 64]\nbuf[1] = 2\n");
+        let [Tree::Let(_, Some(typ), value), Tree::Store(target, _)] = &trees[..] else {
+            panic!("{:?}", trees);
+        };
+        assert_eq!(*typ, Type::Array(Box::new(Type::U8), 64));
+        assert!(matches!(**value, Tree::Empty()));
+        assert!(matches!(**target, Tree::Index(..)));
+    }
+
+    #[test]
+    fn open_ranges_slice_to_the_ends() {
+        let trees = parse("let a = s[1..]\nlet b = s[..2]\nlet n = s.len\n");
+        let values: Vec<&Tree> = trees
+            .iter()
+            .map(|tree| match tree {
+                Tree::Let(_, _, value) => &**value,
+                _ => panic!("{:?}", trees),
+            })
+            .collect();
+        let [Tree::Index(_, from, _), Tree::Index(_, to, _), Tree::Field(_, field)] = &values[..]
+        else {
+            panic!("{:?}", values);
+        };
+        assert!(matches!(&**from, Tree::CmpOp(start, Token::DDot, end)
+            if matches!(**start, Tree::Number(1)) && matches!(**end, Tree::Empty())));
+        assert!(matches!(&**to, Tree::CmpOp(start, Token::DDot, end)
+            if matches!(**start, Tree::Number(0)) && matches!(**end, Tree::Number(2))));
+        assert_eq!(field, "len");
+    }
+
+    #[test]
+    fn struct_literals_know_their_type() {
+        let trees = parse("struct Point { x: i64, y: u8 }\nlet p = Point { y: 2, x: 1 }\n");
+        let Some(Tree::Let(_, _, value)) = trees.last() else {
+            panic!("{:?}", trees);
+        };
+        let Tree::StructLit(typ, fields) = &**value else {
+            panic!("{:?}", value);
+        };
+        assert_eq!(typ.field("y"), Some((8, Type::U8)));
+        let names: Vec<&str> = fields.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["y", "x"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate field x in struct Point")]
+    fn struct_fields_are_unique() {
+        parse("struct Point { x: i64, x: u8 }\n");
+    }
+
+    #[test]
+    fn enum_tags_count_up_like_c() {
+        let trees = parse("enum Color { Red, Green, Blue = 5, Black }\nlet c = Color.Black\n");
+        let Some(Tree::Let(_, _, value)) = trees.last() else {
+            panic!("{:?}", trees);
+        };
+        let Tree::Variant(typ, name, args) = &**value else {
+            panic!("{:?}", value);
+        };
+        assert_eq!((name.as_str(), args.len()), ("Black", 0));
+        let tags: Vec<usize> = ["Red", "Green", "Blue", "Black"]
+            .iter()
+            .map(|name| typ.variant(name).unwrap().tag)
+            .collect();
+        assert_eq!(tags, [0, 1, 5, 6]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate variant Red in enum Color")]
+    fn enum_variants_are_unique() {
+        parse("enum Color { Red, Red }\n");
+    }
+
+    #[test]
+    fn break_leaves_the_innermost_loop() {
+        let trees = parse("loop {\n  do { break } while (1 == 1)\n  break\n}\n");
+        let [Tree::Loop { body, end, .. }] = &trees[..] else {
+            panic!("{:?}", trees);
+        };
+        let [Tree::DoWhile {
+            body: inner,
+            end: inner_end,
+            ..
+        }, Tree::Break(outer)] = &body[..]
+        else {
+            panic!("{:?}", body);
+        };
+        assert!(matches!(inner[..], [Tree::Break(target)] if target == *inner_end));
+        assert_eq!(outer, end);
+        assert_ne!(inner_end, end);
+    }
+
+    #[test]
+    fn break_leaves_a_while_after_its_condition() {
+        let trees = parse("while (1 == 1) { break }\n");
+        let [Tree::While { body, end, .. }] = &trees[..] else {
+            panic!("{:?}", trees);
+        };
+        assert!(matches!(body[..], [Tree::Break(target)] if target == end + 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "break outside of a loop at 2:1")]
+    fn break_needs_a_loop() {
+        parse("let a = 1\nbreak\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected while (..) after do {..}")]
+    fn do_needs_a_while() {
+        parse("do { let a = 1 }\n");
+    }
+
+    #[test]
+    fn defer_takes_a_statement_or_a_block() {
+        let trees = parse("defer write(1, \"a\")\ndefer { let b = 1; write(1, \"b\") }\n");
+        let [Tree::Defer(one), Tree::Defer(block)] = &trees[..] else {
+            panic!("{:?}", trees);
+        };
+        let write = |tree: &Tree| matches!(tree, Tree::Call(name, ..) if name == "write");
+        assert!(matches!(&one[..], [call] if write(call)));
+        assert!(matches!(&block[..], [Tree::Let(..), call] if write(call)));
+    }
+
+    #[test]
+    fn fns_keep_their_signature() {
+        let trees = parse("fn add(a: i64, b: *u8) -> i64 { return a }\n");
+        let [Tree::Fn {
+            name,
+            params,
+            ret,
+            body,
+        }] = &trees[..]
+        else {
+            panic!("{:?}", trees);
+        };
+        assert_eq!(name, "add");
+        assert_eq!(
+            *params,
+            vec![
+                ("a".to_string(), Type::I64),
+                ("b".to_string(), Type::Ptr(Box::new(Type::U8)))
+            ]
+        );
+        assert_eq!(*ret, Some(Type::I64));
+        assert!(matches!(&body[..], [Tree::Return(Some(_))]));
+    }
+
+    #[test]
+    fn module_names_are_qualified_and_private_unless_pub() {
+        let (tokens, locs) =
+            Tokenizer::new("fn hidden() { }\npub fn shown() { }\n".to_string()).tokenize();
+        let context = Context {
+            module: "util".to_string(),
+            ..Context::default()
+        };
+        let mut parser = Parser::new(&tokens, &locs, context);
+        let names: Vec<String> = parser
+            .parse_tokens()
+            .iter()
+            .filter_map(|tree| match tree {
+                Tree::Fn { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, vec!["util.hidden", "util.shown"]);
+        assert_eq!(parser.context().private, vec!["util.hidden"]);
+    }
+
+    #[test]
+    fn format_specs() {
+        let spec = Parser::parse_spec("08x", Loc { line: 1, col: 1 }).unwrap();
+        assert!(spec.zero && spec.hex && spec.width == 8 && spec.left.is_none());
+        let spec = Parser::parse_spec(">6", Loc { line: 1, col: 1 }).unwrap();
+        assert!(!spec.zero && !spec.hex && spec.width == 6 && spec.left == Some(false));
+        let spec = Parser::parse_spec("*<4", Loc { line: 1, col: 1 }).unwrap();
+        assert!(spec.fill == Some('*') && spec.left == Some(true) && spec.width == 4);
+    }
+
+    #[test]
+    fn bad_braces_are_kept_as_errors() {
+        let trees = parse("print(\"{n:4b}\")\nlet s = \"{a b}\"\n");
+        let [Tree::Call(_, args, _), Tree::Let(_, _, literal)] = &trees[..] else {
+            panic!("{:?}", trees);
+        };
+        assert!(matches!(&args[..], [Tree::Format(parts, _)]
+            if matches!(&parts[..], [Part::Error(error)] if error == "Unknown format b in {..:4b} at 1:7")));
+        assert!(matches!(&**literal, Tree::Format(parts, text)
+            if text == "{a b}" && matches!(parts[..], [Part::Error(_)])));
+    }
+
+    #[test]
+    fn interpolated_strings_become_formats() {
+        let trees = parse("print(\"n = {n:>6}\\n\")\nlet s = \"{\"\n");
+        let [Tree::Call(_, args, _), Tree::Let(_, _, literal)] = &trees[..] else {
+            panic!("{:?}", trees);
+        };
+        assert!(matches!(&**literal, Tree::Format(_, text) if text == "{"));
+        let [Tree::Format(parts, _)] = &args[..] else {
+            panic!("{:?}", args);
+        };
+        assert!(matches!(&parts[..], [
+            Part::Lit(before),
+            Part::Expr(Tree::Ident(n), spec),
+            Part::Lit(after),
+        ] if before == "n = " && n == "n" && spec.width == 6 && after == "\n"));
+    }
+
+    #[test]
+    fn operators_know_where_they_are() {
+        let trees = parse("let a = b +\n  c * d\n");
+        let [Tree::Let(_, _, sum)] = &trees[..] else {
+            panic!("{:?}", trees);
+        };
+        let Tree::BinOp(_, Token::Plus, product, plus) = &**sum else {
+            panic!("{:?}", sum);
+        };
+        assert_eq!(plus.to_string(), "1:11");
+        assert!(
+            matches!(&**product, Tree::BinOp(_, Token::Multiply, _, times) if times.to_string() == "2:5")
+        );
+    }
+
+    #[test]
+    fn methods_are_called_on_their_base() {
+        let trees = parse("let v: Vec[i64]\nv.push(1)\nlet s: String\n");
+        let [Tree::Let(_, Some(vec), _), Tree::Method(base, method, args, _), Tree::Let(_, Some(string), _)] =
+            &trees[..]
+        else {
+            panic!("{:?}", trees);
+        };
+        assert_eq!(*vec, Type::Vec(Box::new(Type::I64)));
+        assert_eq!(*string, Type::Vec(Box::new(Type::U8)));
+        assert!(matches!(&**base, Tree::Ident(v) if v == "v"));
+        assert_eq!(method, "push");
+        assert!(matches!(&args[..], [Tree::Number(1)]));
+    }
+
+    #[test]
+    fn break_leaves_a_for_past_its_step() {
+        let trees = parse("for i -> 0..3 {\nbreak\n}\n");
+        let [Tree::For { body, end, .. }] = &trees[..] else {
+            panic!("{:?}", trees);
+        };
+        assert!(matches!(&body[..], [Tree::Break(label)] if *label == end + 1));
+    }
+
+    #[test]
+    fn asm_binds_registers_around_its_lines() {
+        let trees = parse(
+            "asm(rax = 47, rcx = n) [rdx] { \"xor edx, edx\" \"div rcx\" } -> q = rax, r = rdx\nasm { \"rdtsc\" } -> t\n",
+        );
+        let [Tree::Asm {
+            lines,
+            inputs,
+            clobbers,
+            outputs,
+            ..
+        }, Tree::Asm { outputs: short, .. }] = &trees[..]
+        else {
+            panic!("{:?}", trees);
+        };
+        assert_eq!(*lines, vec!["xor edx, edx", "div rcx"]);
+        assert!(
+            matches!(&inputs[..], [(rax, Tree::Number(47)), (rcx, Tree::Ident(_))] if rax == "rax" && rcx == "rcx")
+        );
+        assert_eq!(*clobbers, vec!["rdx"]);
+        let pair = |var: &str, reg: &str| (var.to_string(), reg.to_string());
+        assert_eq!(*outputs, vec![pair("q", "rax"), pair("r", "rdx")]);
+        assert_eq!(*short, vec![pair("t", "rax")]);
+    }
+
+    #[test]
+    fn star_on_a_new_line_continues_the_expression() {
+        let trees = parse("let x = a\n    * b\n");
+        let [Tree::Let(_, _, expr)] = &trees[..] else {
+            panic!("{:?}", trees);
+        };
+        assert!(matches!(**expr, Tree::BinOp(_, Token::Multiply, ..)));
+    }
+
+    #[test]
+    fn star_before_an_assignment_starts_a_store() {
+        let trees = parse("let x = a\n*p = 5\nx = x * 2\n*(p + 1) = 6\n**pp = 7\n");
+        assert!(
+            matches!(
+                &trees[..],
+                [
+                    Tree::Let(..),
+                    Tree::Store(..),
+                    Tree::Assign(..),
+                    Tree::Store(..),
+                    Tree::Store(..)
+                ]
+            ),
+            "{:?}",
+            trees
+        );
+    }
+
+    #[test]
+    fn exit_is_an_ordinary_name() {
+        let trees = parse("fn exit(code: i64) {\n}\nexit(3)\n");
+        assert!(
+            matches!(
+                &trees[..],
+                [Tree::Fn { name, .. }, Tree::Call(call, ..)] if name == "exit" && call == "exit"
+            ),
+            "{:?}",
+            trees
+        );
+    }
+
+    #[test]
+    fn builtin_names_are_not_reserved() {
+        let trees = parse("fn len(x: i64) -> i64 { return x }\nlet write = null\nlet t = true\n");
+        assert!(
+            matches!(
+                &trees[..],
+                [
+                    Tree::Fn { name, .. },
+                    Tree::Let(write, None, null),
+                    Tree::Let(_, None, t),
+                ] if name == "len"
+                    && write == "write"
+                    && matches!(**null, Tree::Null)
+                    && matches!(**t, Tree::Bool(true))
+            ),
+            "{:?}",
+            trees
+        );
+    }
+}
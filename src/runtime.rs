@@ -0,0 +1,486 @@
+// hand written routines the generator emits once, only when a program needs them
+
+pub static BOUNDS_FAIL: &str = "\
+__bounds_fail:
+\t;; write(2, rsi, rdx) then exit(1) ;;
+\tmov rax, 1
+\tmov rdi, 2
+\tsyscall
+\tmov rax, 60
+\tmov rdi, 1
+\tsyscall
+";
+
+// __print_int writes rax as a signed decimal to stdout, __print_uint as an unsigned one
+pub static PRINT_INT: &str = "\
+__print_int:
+\t;; r8 keeps the sign ;;
+\tmov r8, rax
+\ttest rax, rax
+\tjns __print_int_digits
+\tneg rax
+\tjmp __print_int_digits
+__print_uint:
+\txor r8d, r8d
+__print_int_digits:
+\tsub rsp, 32
+\tlea rsi, [rsp + 32]
+\tmov rcx, 10
+__print_int_loop:
+\txor edx, edx
+\tdiv rcx
+\tadd dl, 48
+\tdec rsi
+\tmov BYTE [rsi], dl
+\ttest rax, rax
+\tjnz __print_int_loop
+\ttest r8, r8
+\tjns __print_int_write
+\tdec rsi
+\tmov BYTE [rsi], 45
+__print_int_write:
+\t;; write(1, rsi, end - rsi) ;;
+\tlea rdx, [rsp + 32]
+\tsub rdx, rsi
+\tmov rax, 1
+\tmov rdi, 1
+\tsyscall
+\tadd rsp, 32
+\tret
+";
+
+// writes rax in lowercase hex to stdout, without a prefix
+pub static PRINT_HEX: &str = "\
+__print_hex:
+\tsub rsp, 32
+\tlea rsi, [rsp + 32]
+__print_hex_loop:
+\tmov rdx, rax
+\tand edx, 15
+\tmov dl, BYTE [__hex_digits + rdx]
+\tdec rsi
+\tmov BYTE [rsi], dl
+\tshr rax, 4
+\tjnz __print_hex_loop
+\t;; write(1, rsi, end - rsi) ;;
+\tlea rdx, [rsp + 32]
+\tsub rdx, rsi
+\tmov rax, 1
+\tmov rdi, 1
+\tsyscall
+\tadd rsp, 32
+\tret
+";
+
+// shared by the hex and decimal routines
+pub static HEX_DIGITS: &str = "\
+__hex_digits:
+\tdb \"0123456789abcdef\"
+";
+
+// writes the digits of rax in base rcx backwards from rdi, rsi and rdx are the text left behind
+// r8 is 1 when rax is signed, r9 is the width to pad with zeros after the sign
+pub static FMT_INT: &str = "\
+__fmt_int:
+\tmov rsi, rdi
+\txor r10d, r10d
+\ttest r8, r8
+\tjz __fmt_int_loop
+\ttest rax, rax
+\tjns __fmt_int_loop
+\tneg rax
+\tmov r10d, 1
+__fmt_int_loop:
+\txor edx, edx
+\tdiv rcx
+\tmov dl, BYTE [__hex_digits + rdx]
+\tdec rsi
+\tmov BYTE [rsi], dl
+\ttest rax, rax
+\tjnz __fmt_int_loop
+\t;; the sign counts towards the width, the 32 byte buffer caps it ;;
+\tsub r9, r10
+__fmt_int_zeros:
+\tmov rdx, rdi
+\tsub rdx, rsi
+\tcmp rdx, r9
+\tjge __fmt_int_sign
+\tcmp rdx, 31
+\tjge __fmt_int_sign
+\tdec rsi
+\tmov BYTE [rsi], 48
+\tjmp __fmt_int_zeros
+__fmt_int_sign:
+\ttest r10, r10
+\tjz __fmt_int_done
+\tdec rsi
+\tmov BYTE [rsi], 45
+__fmt_int_done:
+\tmov rdx, rdi
+\tsub rdx, rsi
+\tret
+";
+
+// write(1, rsi, rdx) padded up to rcx bytes with the byte in r8, after the text when r9 is 1
+pub static WRITE_PAD: &str = "\
+__write_pad:
+\tsub rsp, 96
+\tmov QWORD [rsp + 64], rsi
+\tmov QWORD [rsp + 72], rdx
+\tsub rcx, rdx
+\tmov QWORD [rsp + 80], rcx
+\tmov QWORD [rsp + 88], r9
+\t;; 64 bytes of padding to write from ;;
+\tmov rdi, rsp
+\tmov eax, r8d
+\tmov ecx, 64
+\trep stosb
+\ttest r9, r9
+\tjz __write_pad_fill
+__write_pad_text:
+\tmov rax, 1
+\tmov rdi, 1
+\tmov rsi, QWORD [rsp + 64]
+\tmov rdx, QWORD [rsp + 72]
+\tsyscall
+\tcmp QWORD [rsp + 88], 0
+\tjz __write_pad_done
+__write_pad_fill:
+\tmov rdx, QWORD [rsp + 80]
+\tcmp rdx, 0
+\tjle __write_pad_filled
+\tcmp rdx, 64
+\tjle __write_pad_chunk
+\tmov rdx, 64
+__write_pad_chunk:
+\tsub QWORD [rsp + 80], rdx
+\tmov rax, 1
+\tmov rdi, 1
+\tmov rsi, rsp
+\tsyscall
+\tjmp __write_pad_fill
+__write_pad_filled:
+\tcmp QWORD [rsp + 88], 0
+\tjz __write_pad_text
+__write_pad_done:
+\tadd rsp, 96
+\tret
+";
+
+// not a routine, _start runs it before the variables are allocated: the command line
+// becomes a []str below the initial stack, so args() doesn't have to measure them
+pub static ARGS: &str = "\
+\t;; __args = []str of argv ;;
+\tmov rcx, QWORD [rsp]
+\tlea rbx, [rsp + 8]
+\tmov rax, rcx
+\tshl rax, 4
+\tsub rsp, rax
+\tmov QWORD [__args], rsp
+\tmov QWORD [__args + 8], rcx
+\tmov rdi, rsp
+__args_next:
+\ttest rcx, rcx
+\tjz __args_done
+\tmov rsi, QWORD [rbx]
+\txor edx, edx
+__args_len:
+\tcmp BYTE [rsi + rdx], 0
+\tje __args_store
+\tinc rdx
+\tjmp __args_len
+__args_store:
+\tmov QWORD [rdi], rsi
+\tmov QWORD [rdi + 8], rdx
+\tadd rdi, 16
+\tadd rbx, 8
+\tdec rcx
+\tjmp __args_next
+__args_done:
+";
+
+// looks up the environment variable named by rsi, rdx and leaves its value in rax, rdx,
+// a missing one is an empty str
+pub static ENV: &str = "\
+__env:
+\t;; envp starts after argv and its null ;;
+\tmov rbx, QWORD [__initial_rsp]
+\tmov rcx, QWORD [rbx]
+\tlea rbx, [rbx + rcx*8 + 16]
+__env_next:
+\tmov rdi, QWORD [rbx]
+\ttest rdi, rdi
+\tjz __env_missing
+\tadd rbx, 8
+\txor ecx, ecx
+__env_name:
+\tcmp rcx, rdx
+\tje __env_equals
+\tmov al, BYTE [rdi + rcx]
+\tcmp al, BYTE [rsi + rcx]
+\tjne __env_next
+\tinc rcx
+\tjmp __env_name
+__env_equals:
+\t;; NAME=value ;;
+\tcmp BYTE [rdi + rcx], 61
+\tjne __env_next
+\tlea rax, [rdi + rcx + 1]
+\txor edx, edx
+__env_len:
+\tcmp BYTE [rax + rdx], 0
+\tje __env_done
+\tinc rdx
+\tjmp __env_len
+__env_missing:
+\txor eax, eax
+\txor edx, edx
+__env_done:
+\tret
+";
+
+// writes the location in rsi, rdx and the message in r8, r9 to stderr, then exit(101)
+pub static PANIC: &str = "\
+__panic:
+\tpush r8
+\tpush r9
+\tmov rax, 1
+\tmov rdi, 2
+\tsyscall
+\tpop rdx
+\tpop rsi
+\tmov rax, 1
+\tmov rdi, 2
+\tsyscall
+\tpush 10
+\tmov rax, 1
+\tmov rdi, 2
+\tmov rsi, rsp
+\tmov rdx, 1
+\tsyscall
+\tmov rax, 60
+\tmov rdi, 101
+\tsyscall
+";
+
+// heap blocks start with a 16 byte header: the payload size, then 1 while in use and 2 once freed.
+// __alloc takes the size in rdi and leaves the payload in rax, or null when mmap fails.
+// freed blocks are reused first fit, otherwise the block is bumped from the current chunk
+pub static ALLOC: &str = "\
+__alloc:
+\t;; rounded up to 16, at least 16 ;;
+\tadd rdi, 15
+\tand rdi, -16
+\tjnz __alloc_search
+\tmov rdi, 16
+__alloc_search:
+\t;; rcx is the link that points at the block ;;
+\tmov rcx, __heap_free
+__alloc_next:
+\tmov rax, QWORD [rcx]
+\ttest rax, rax
+\tjz __alloc_bump
+\tcmp QWORD [rax - 16], rdi
+\tjae __alloc_reuse
+\tmov rcx, rax
+\tjmp __alloc_next
+__alloc_reuse:
+\tmov rdx, QWORD [rax]
+\tmov QWORD [rcx], rdx
+\tmov QWORD [rax - 8], 1
+\tret
+__alloc_bump:
+\tmov rax, QWORD [__heap_next]
+\tlea rdx, [rax + rdi + 16]
+\tcmp rdx, QWORD [__heap_end]
+\tjbe __alloc_take
+\t;; mmap(0, at least 1MiB, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0) ;;
+\tpush rdi
+\tlea rsi, [rdi + 16 + 0xfffff]
+\tand rsi, -0x100000
+\tpush rsi
+\tmov rax, 9
+\txor edi, edi
+\tmov rdx, 3
+\tmov r10, 34
+\tmov r8, -1
+\txor r9d, r9d
+\tsyscall
+\tpop rsi
+\tpop rdi
+\tcmp rax, -4096
+\tja __alloc_failed
+\tlea rdx, [rax + rsi]
+\tmov QWORD [__heap_end], rdx
+\tlea rdx, [rax + rdi + 16]
+__alloc_take:
+\tmov QWORD [__heap_next], rdx
+\tmov QWORD [rax], rdi
+\tmov QWORD [rax + 8], 1
+\tadd rax, 16
+\tret
+__alloc_failed:
+\txor eax, eax
+\tret
+";
+
+// __realloc takes the block in rdi and the new size in rsi, the block is moved only when it
+// doesn't fit. the debug __free gets the location in rdx, rcx
+pub static REALLOC: &str = "\
+__realloc:
+\ttest rdi, rdi
+\tjnz __realloc_block
+\tmov rdi, rsi
+\tjmp __alloc
+__realloc_block:
+\tmov rax, rdi
+\tcmp QWORD [rdi - 16], rsi
+\tjae __realloc_done
+\tpush rcx
+\tpush rdx
+\tpush rdi
+\tmov rdi, rsi
+\tcall __alloc
+\ttest rax, rax
+\tjz __realloc_failed
+\t;; copy the old payload ;;
+\tmov rsi, QWORD [rsp]
+\tmov rdi, rax
+\tmov rcx, QWORD [rsi - 16]
+\trep movsb
+\tpop rdi
+\tpop rsi
+\tpop rdx
+\tpush rax
+\tcall __free
+\tpop rax
+__realloc_done:
+\tret
+__realloc_failed:
+\tadd rsp, 24
+\tret
+";
+
+// __free takes the block in rdi and pushes it on the free list
+pub static FREE: &str = "\
+__free:
+\ttest rdi, rdi
+\tjz __free_done
+\tmov QWORD [rdi - 8], 2
+\tmov rax, QWORD [__heap_free]
+\tmov QWORD [rdi], rax
+\tmov QWORD [__heap_free], rdi
+__free_done:
+\tret
+";
+
+// with --debug-alloc, __free also gets the location in rsi, rdx to panic with on a double free,
+// and fills freed blocks with 0xdd
+pub static FREE_DEBUG: &str = "\
+__free:
+\ttest rdi, rdi
+\tjz __free_done
+\tcmp QWORD [rdi - 8], 1
+\tje __free_block
+\tmov r8, __free_double
+\tmov r9, 11
+\tcmp QWORD [rdi - 8], 2
+\tje __panic
+\tmov r8, __free_unknown
+\tmov r9, 37
+\tjmp __panic
+__free_block:
+\tmov QWORD [rdi - 8], 2
+\tmov r8, rdi
+\tmov rcx, QWORD [rdi - 16]
+\tmov al, 0xdd
+\trep stosb
+\tmov rdi, r8
+\tmov rax, QWORD [__heap_free]
+\tmov QWORD [rdi], rax
+\tmov QWORD [__heap_free], rdi
+__free_done:
+\tret
+__free_double: db \"double free\"
+__free_unknown: db \"free of a pointer alloc didn't return\"
+";
+
+// makes room for rdx more elements of rsi bytes in the Vec at rdi, which is kept in rdi.
+// the capacity at least doubles so pushes stay cheap, r8 and r9 are where to report running out
+pub static VEC_RESERVE: &str = "\
+__vec_reserve:
+\tmov rax, QWORD [rdi + 8]
+\tadd rax, rdx
+\tcmp rax, QWORD [rdi + 16]
+\tjbe __vec_reserve_done
+\tmov rcx, QWORD [rdi + 16]
+\tadd rcx, rcx
+\tcmp rcx, rax
+\tjae __vec_reserve_min
+\tmov rcx, rax
+__vec_reserve_min:
+\tcmp rcx, 8
+\tjae __vec_reserve_grow
+\tmov rcx, 8
+__vec_reserve_grow:
+\tpush r8
+\tpush r9
+\tpush rdi
+\tpush rcx
+\timul rsi, rcx
+\tmov rdi, QWORD [rdi]
+\t;; no location for the debug __free ;;
+\txor edx, edx
+\txor ecx, ecx
+\tcall __realloc
+\tpop rcx
+\tpop rdi
+\tpop r9
+\tpop r8
+\ttest rax, rax
+\tjz __vec_reserve_failed
+\tmov QWORD [rdi], rax
+\tmov QWORD [rdi + 16], rcx
+__vec_reserve_done:
+\tret
+__vec_reserve_failed:
+\tmov rsi, r8
+\tmov rdx, r9
+\tmov r8, __vec_reserve_oom
+\tmov r9, 17
+\tjmp __panic
+__vec_reserve_oom: db \"allocation failed\"
+";
+
+// rax is 0 when the str in rsi, rdx has the same bytes as the one in rdi, rcx
+pub static STR_EQ: &str = "\
+__str_eq:
+\tmov eax, 1
+\t;; the lengths first, equal empty strs leave ZF set ;;
+\tcmp rdx, rcx
+\tjne __str_eq_done
+\trepe cmpsb
+\tsetne al
+__str_eq_done:
+\tret
+";
+
+// compares the str in rsi, rdx with the one in rdi, rcx, rax is negative, 0 or positive like
+// the first byte that differs, or like the lengths when one starts with the other
+pub static STR_CMP: &str = "\
+__str_cmp:
+\tmov r8, rdx
+\tsub r8, rcx
+\tcmp rdx, rcx
+\tcmovb rcx, rdx
+\ttest rcx, rcx
+\trepe cmpsb
+\tje __str_cmp_prefix
+\tmovzx eax, BYTE [rsi - 1]
+\tmovzx edx, BYTE [rdi - 1]
+\tsub rax, rdx
+\tret
+__str_cmp_prefix:
+\tmov rax, r8
+\tret
+";
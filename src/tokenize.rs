@@ -20,20 +20,85 @@ pub enum Token {
     CloseParen,
     OpenCurly,
     CloseCurly,
+    OpenBracket,
+    CloseBracket,
     Comma,
+    Colon,
     Semi,
     Dot,
     DDot,
     ThinArrow,
+    FatArrow,
+    Ampersand,
     Let,
-    Exit,
+    Static,
+    Const,
+    Struct,
+    Enum,
+    Match,
     Ident(String),
     If,
     Els,
     ElsIf,
     While,
+    Loop,
+    Do,
+    Break,
+    Defer,
     For,
+    Fn,
+    Return,
+    Pub,
+    Import,
     SysCall,
+    Asm,
+    // "a {x:>4} b" is FmtStart("a {x:>4} b") String("a ") FmtOpen Ident("x") FmtSpec(">4") FmtClose
+    // String(" b") FmtEnd, FmtStart keeps the text as written
+    FmtStart(String),
+    FmtOpen,
+    FmtSpec(String),
+    FmtClose,
+    FmtEnd,
+    FmtError(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loc {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl std::fmt::Display for Loc {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+// a peekable char iterator that keeps track of the current line and column
+struct Cursor<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    loc: Loc,
+}
+
+impl Cursor<'_> {
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.loc.line += 1;
+            self.loc.col = 1;
+        } else {
+            self.loc.col += 1;
+        }
+        Some(c)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -45,11 +110,21 @@ impl Tokenizer {
         Tokenizer { input }
     }
 
-    pub fn tokenize(self) -> Vec<Token> {
+    pub fn tokenize(self) -> (Vec<Token>, Vec<Loc>) {
+        Self::scan(&self.input, Loc { line: 1, col: 1 })
+    }
+
+    // `start` is where `input` begins in the file, interpolations are scanned on their own
+    fn scan(input: &str, start: Loc) -> (Vec<Token>, Vec<Loc>) {
         let mut tokens = Vec::new();
-        let mut iter = self.input.chars().peekable();
+        let mut locs = Vec::new();
+        let mut iter = Cursor {
+            chars: input.chars().peekable(),
+            loc: start,
+        };
 
         while let Some(&c) = iter.peek() {
+            let loc = iter.loc;
             match c {
                 'a'..='z' | '_' | 'A'..='Z' => {
                     let mut buf = String::new();
@@ -62,21 +137,34 @@ impl Tokenizer {
                         }
                     }
                     match buf.as_str() {
-                        "exit" => tokens.push(Token::Exit),
                         "let" => tokens.push(Token::Let),
+                        "static" => tokens.push(Token::Static),
+                        "const" => tokens.push(Token::Const),
+                        "struct" => tokens.push(Token::Struct),
+                        "enum" => tokens.push(Token::Enum),
+                        "match" => tokens.push(Token::Match),
                         "if" => tokens.push(Token::If),
                         "els" => tokens.push(Token::Els),
                         "elsif" => tokens.push(Token::ElsIf),
                         "while" => tokens.push(Token::While),
+                        "loop" => tokens.push(Token::Loop),
+                        "do" => tokens.push(Token::Do),
+                        "break" => tokens.push(Token::Break),
+                        "defer" => tokens.push(Token::Defer),
+                        "fn" => tokens.push(Token::Fn),
+                        "return" => tokens.push(Token::Return),
+                        "pub" => tokens.push(Token::Pub),
+                        "import" => tokens.push(Token::Import),
                         "for" => tokens.push(Token::For),
                         "syscall" => tokens.push(Token::SysCall),
+                        "asm" => tokens.push(Token::Asm),
                         _ => tokens.push(Token::Ident(buf)),
                     }
                 }
                 '0'..='9' => {
                     let mut number = String::new();
                     while let Some(&c) = iter.peek() {
-                        if c.is_digit(10) {
+                        if c.is_ascii_digit() {
                             number.push(c);
                             iter.next();
                         } else {
@@ -88,28 +176,95 @@ impl Tokenizer {
                 }
                 '\"' => {
                     iter.next();
+                    // braces are scanned the same in every string, the text as written is kept
+                    // for callees that don't interpolate, which the generator resolves
                     let mut string = String::new();
-                    while let Some(&c) = iter.peek() {
+                    let mut raw = String::new();
+                    // the tokens between FmtStart and FmtEnd once there's a brace
+                    let mut parts: Vec<(Token, Loc)> = vec![];
+                    while let Some(c) = iter.next() {
+                        if c == '\"' {
+                            break;
+                        }
+                        raw.push(c);
                         match c {
-                            '\"' => {
-                                iter.next();
-                                break;
-                            }
                             '\\' => {
                                 string.push(c);
-                                iter.next();
-                                if *iter.peek().unwrap() == '\"' {
+                                if iter.peek() == Some(&'\"') {
                                     string.push('\"');
+                                    raw.push('\"');
                                     iter.next();
                                 }
                             }
-                            _ => {
+                            // `{{` and `}}` are literal braces
+                            '{' | '}' if iter.peek() == Some(&c) => {
                                 string.push(c);
+                                raw.push(c);
+                                iter.next();
+                            }
+                            // bad braces are only an error if the string gets printed
+                            '}' => parts.push((
+                                Token::FmtError(format!(
+                                    "Unmatched }} in string at {}, use }}}}",
+                                    iter.loc
+                                )),
+                                loc,
+                            )),
+                            '{' => {
+                                let start = iter.loc;
+                                let mut expr = String::new();
+                                let mut spec = None;
+                                while let Some(&c) = iter.peek() {
+                                    if c == '}' || c == '\"' {
+                                        break;
+                                    }
+                                    raw.push(c);
+                                    iter.next();
+                                    match &mut spec {
+                                        None if c == ':' => spec = Some(String::new()),
+                                        Some(spec) => spec.push(c),
+                                        None => expr.push(c),
+                                    }
+                                }
+                                if iter.peek() != Some(&'}') {
+                                    let error = format!("Unterminated {{ in string at {}", start);
+                                    parts.push((Token::FmtError(error), loc));
+                                    continue;
+                                }
+                                raw.push('}');
                                 iter.next();
+                                let (expr_tokens, expr_locs) = Self::scan(&expr, start);
+                                if expr_tokens.is_empty() {
+                                    let error = format!("Empty {{}} in string at {}", start);
+                                    parts.push((Token::FmtError(error), loc));
+                                    continue;
+                                }
+                                if !string.is_empty() {
+                                    parts.push((Token::String(std::mem::take(&mut string)), loc));
+                                }
+                                parts.push((Token::FmtOpen, loc));
+                                parts.extend(expr_tokens.into_iter().zip(expr_locs));
+                                if let Some(spec) = spec {
+                                    parts.push((Token::FmtSpec(spec), loc));
+                                }
+                                parts.push((Token::FmtClose, loc));
                             }
+                            _ => string.push(c),
+                        }
+                    }
+                    if parts.is_empty() && string == raw {
+                        tokens.push(Token::String(string));
+                    } else {
+                        if !string.is_empty() {
+                            parts.push((Token::String(string), loc));
+                        }
+                        parts.insert(0, (Token::FmtStart(raw), loc));
+                        parts.push((Token::FmtEnd, loc));
+                        for (token, at) in parts {
+                            tokens.push(token);
+                            locs.push(at);
                         }
                     }
-                    tokens.push(Token::String(string));
                 }
                 '(' => {
                     tokens.push(Token::OpenParen);
@@ -127,6 +282,14 @@ impl Tokenizer {
                     tokens.push(Token::CloseCurly);
                     iter.next();
                 }
+                '[' => {
+                    tokens.push(Token::OpenBracket);
+                    iter.next();
+                }
+                ']' => {
+                    tokens.push(Token::CloseBracket);
+                    iter.next();
+                }
                 '+' => {
                     iter.next();
                     if *iter.peek().unwrap() == '+' {
@@ -166,11 +329,16 @@ impl Tokenizer {
                 }
                 '=' => {
                     iter.next();
-                    if *iter.peek().unwrap() == '=' {
-                        tokens.push(Token::EquEqu);
-                        iter.next();
-                    } else {
-                        tokens.push(Token::Equal);
+                    match iter.peek() {
+                        Some('=') => {
+                            tokens.push(Token::EquEqu);
+                            iter.next();
+                        }
+                        Some('>') => {
+                            tokens.push(Token::FatArrow);
+                            iter.next();
+                        }
+                        _ => tokens.push(Token::Equal),
                     }
                 }
                 '!' => {
@@ -214,6 +382,14 @@ impl Tokenizer {
                     iter.next();
                     tokens.push(Token::Comma);
                 }
+                ':' => {
+                    iter.next();
+                    tokens.push(Token::Colon);
+                }
+                '&' => {
+                    iter.next();
+                    tokens.push(Token::Ampersand);
+                }
                 ';' => {
                     tokens.push(Token::Semi);
                     iter.next();
@@ -222,8 +398,105 @@ impl Tokenizer {
                     iter.next();
                 }
             }
+            locs.resize(tokens.len(), loc);
         }
 
-        tokens
+        (tokens, locs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<String> {
+        let (tokens, _) = Tokenizer::new(input.to_string()).tokenize();
+        tokens.iter().map(|token| format!("{:?}", token)).collect()
+    }
+
+    #[test]
+    fn every_token_has_a_location() {
+        let (tokens, locs) = Tokenizer::new("let a = 1\n  a[0]\n".to_string()).tokenize();
+        assert_eq!(tokens.len(), locs.len());
+        let at: Vec<String> = locs.iter().map(|loc| loc.to_string()).collect();
+        assert_eq!(at[..4], ["1:1", "1:5", "1:7", "1:9"]);
+        assert!(at.contains(&"2:3".to_string()));
+        assert!(at.contains(&"2:4".to_string()));
+    }
+
+    #[test]
+    fn interpolations_keep_their_specs() {
+        assert_eq!(
+            tokens("print(\"{x:08x}|{n:>6}\")\n"),
+            [
+                "Ident(\"print\")",
+                "OpenParen",
+                "FmtStart(\"{x:08x}|{n:>6}\")",
+                "FmtOpen",
+                "Ident(\"x\")",
+                "FmtSpec(\"08x\")",
+                "FmtClose",
+                "String(\"|\")",
+                "FmtOpen",
+                "Ident(\"n\")",
+                "FmtSpec(\">6\")",
+                "FmtClose",
+                "FmtEnd",
+                "CloseParen",
+            ]
+        );
+    }
+
+    #[test]
+    fn doubled_braces_keep_the_text_as_written() {
+        assert_eq!(
+            tokens("\"{{x}}\"\n"),
+            ["FmtStart(\"{{x}}\")", "String(\"{x}\")", "FmtEnd"]
+        );
+        assert_eq!(tokens("\"x\"\n"), ["String(\"x\")"]);
+    }
+
+    #[test]
+    fn interpolations_are_located_in_the_string() {
+        let (tokens, locs) = Tokenizer::new("print(\"ab {x}\")\n".to_string()).tokenize();
+        let x = tokens
+            .iter()
+            .position(|token| matches!(token, Token::Ident(name) if name == "x"))
+            .unwrap();
+        assert_eq!(locs[x].to_string(), "1:12");
+    }
+
+    #[test]
+    fn bad_braces_become_errors() {
+        assert_eq!(
+            tokens("\"a}\"\n\"{x\"\n\"{}\"\n"),
+            [
+                "FmtStart(\"a}\")",
+                "FmtError(\"Unmatched } in string at 1:4, use }}\")",
+                "String(\"a\")",
+                "FmtEnd",
+                "FmtStart(\"{x\")",
+                "FmtError(\"Unterminated { in string at 2:3\")",
+                "FmtEnd",
+                "FmtStart(\"{}\")",
+                "FmtError(\"Empty {} in string at 3:3\")",
+                "FmtEnd",
+            ]
+        );
+    }
+
+    #[test]
+    fn strings_are_scanned_the_same_everywhere() {
+        let string = |input: &str| {
+            let tokens = tokens(input);
+            let start = tokens
+                .iter()
+                .position(|token| token.starts_with("FmtStart"));
+            let end = tokens.iter().position(|token| token == "FmtEnd");
+            tokens[start.unwrap()..=end.unwrap()].to_vec()
+        };
+        let printed = string("print(\"{x}\")\n");
+        assert_eq!(string("io.print(\"{x}\")\n"), printed);
+        assert_eq!(string("let s = \"{x}\"\n"), printed);
     }
 }
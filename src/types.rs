@@ -0,0 +1,327 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    // a byte that is 0 or 1, what comparisons produce
+    Bool,
+    Ptr(Box<Type>),
+    Array(Box<Type>, usize),
+    // pointer + length pairs
+    Str,
+    Slice(Box<Type>),
+    // pointer, length and capacity of a heap buffer that grows, String is a Vec[u8]
+    Vec(Box<Type>),
+    // fields are laid out in order with C alignment rules
+    Struct {
+        name: String,
+        fields: Vec<(String, Type)>,
+    },
+    // a tag, followed by the payload of the variant when any variant has one
+    Enum {
+        name: String,
+        variants: Vec<Variant>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: String,
+    pub tag: usize,
+    pub fields: Vec<Type>,
+}
+
+impl Variant {
+    // payload fields are laid out like a struct after the 8 byte tag
+    pub fn offsets(&self) -> Vec<usize> {
+        let mut end: usize = 8;
+        self.fields
+            .iter()
+            .map(|typ| {
+                let offset = end.next_multiple_of(typ.align());
+                end = offset + typ.size();
+                offset
+            })
+            .collect()
+    }
+}
+
+impl Type {
+    pub fn size(&self) -> usize {
+        match self {
+            Type::I8 | Type::U8 | Type::Bool => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 => 4,
+            Type::I64 | Type::U64 | Type::Ptr(_) => 8,
+            Type::Array(typ, len) => typ.size() * len,
+            Type::Str | Type::Slice(_) => 16,
+            Type::Vec(_) => 24,
+            Type::Struct { fields, .. } => {
+                let end = fields.last().map_or(0, |(name, typ)| {
+                    self.field(name)
+                        .map_or(0, |(offset, _)| offset + typ.size())
+                });
+                end.next_multiple_of(self.align())
+            }
+            Type::Enum { variants, .. } => variants
+                .iter()
+                .map(|variant| {
+                    let offsets = variant.offsets();
+                    variant
+                        .fields
+                        .last()
+                        .map_or(8, |typ| offsets.last().unwrap() + typ.size())
+                })
+                .max()
+                .unwrap_or(8)
+                .next_multiple_of(8),
+        }
+    }
+
+    pub fn align(&self) -> usize {
+        match self {
+            Type::Array(typ, _) => typ.align(),
+            Type::Str | Type::Slice(_) | Type::Vec(_) | Type::Enum { .. } => 8,
+            Type::Struct { fields, .. } => {
+                fields.iter().map(|(_, typ)| typ.align()).max().unwrap_or(1)
+            }
+            _ => self.size(),
+        }
+    }
+
+    // offset and type of a struct field
+    pub fn field(&self, name: &str) -> Option<(usize, Type)> {
+        let Type::Struct { fields, .. } = self else {
+            return None;
+        };
+        let mut offset: usize = 0;
+        for (field, typ) in fields {
+            offset = offset.next_multiple_of(typ.align());
+            if field == name {
+                return Some((offset, typ.clone()));
+            }
+            offset += typ.size();
+        }
+        None
+    }
+
+    // stack slots are 8 bytes wide
+    pub fn slots(&self) -> usize {
+        self.size().div_ceil(8).max(1)
+    }
+
+    // arrays used as values turn into a pointer to their first element
+    pub fn decay(&self) -> Type {
+        match self {
+            Type::Array(typ, _) | Type::Slice(typ) => Type::Ptr(typ.clone()),
+            Type::Str => Type::Ptr(Box::new(Type::U8)),
+            _ => self.clone(),
+        }
+    }
+
+    pub fn is_int(&self) -> bool {
+        self.is_signed() || matches!(self, Type::U8 | Type::U16 | Type::U32 | Type::U64)
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::I8 | Type::I16 | Type::I32 | Type::I64)
+    }
+
+    pub fn is_fat(&self) -> bool {
+        matches!(self, Type::Str | Type::Slice(_))
+    }
+
+    // str, []u8 and String, which compare and concatenate as bytes
+    pub fn is_text(&self) -> bool {
+        match self {
+            Type::Str => true,
+            Type::Slice(elem) | Type::Vec(elem) => **elem == Type::U8,
+            _ => false,
+        }
+    }
+
+    // values that live in memory and are used through their address
+    pub fn is_aggregate(&self) -> bool {
+        match self {
+            Type::Array(..) | Type::Struct { .. } | Type::Vec(_) => true,
+            // enums without payloads are just their tag
+            Type::Enum { variants, .. } => variants.iter().any(|v| !v.fields.is_empty()),
+            _ => false,
+        }
+    }
+
+    pub fn variant(&self, name: &str) -> Option<&Variant> {
+        match self {
+            Type::Enum { variants, .. } => variants.iter().find(|v| v.name == name),
+            _ => None,
+        }
+    }
+
+    // element type of anything that can be indexed
+    pub fn elem(&self) -> Option<Type> {
+        match self {
+            Type::Array(typ, _) | Type::Slice(typ) | Type::Ptr(typ) | Type::Vec(typ) => {
+                Some(*typ.clone())
+            }
+            Type::Str => Some(Type::U8),
+            _ => None,
+        }
+    }
+
+    pub fn pointee(&self) -> Option<&Type> {
+        match self {
+            Type::Ptr(typ) => Some(typ),
+            _ => None,
+        }
+    }
+
+    // the struct behind a struct or a pointer to one, fields are reached through both
+    pub fn as_struct(&self) -> Option<&Type> {
+        match self {
+            Type::Struct { .. } => Some(self),
+            Type::Ptr(typ) if matches!(**typ, Type::Struct { .. }) => Some(typ),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arrays_take_whole_stack_slots() {
+        let bytes = Type::Array(Box::new(Type::U8), 12);
+        assert_eq!(bytes.size(), 12);
+        assert_eq!(bytes.slots(), 2);
+        assert_eq!(Type::Array(Box::new(Type::I64), 3).slots(), 3);
+        assert_eq!(Type::U8.slots(), 1);
+    }
+
+    #[test]
+    fn arrays_decay_to_a_pointer_to_their_first_element() {
+        let bytes = Type::Array(Box::new(Type::U8), 12);
+        assert_eq!(bytes.decay(), Type::Ptr(Box::new(Type::U8)));
+        assert_eq!(Type::I64.decay(), Type::I64);
+    }
+
+    #[test]
+    fn strs_and_slices_are_a_pointer_and_a_length() {
+        let bytes = Type::Slice(Box::new(Type::U8));
+        assert_eq!((Type::Str.size(), bytes.size()), (16, 16));
+        assert!(Type::Str.is_fat() && bytes.is_fat());
+        assert!(!Type::Array(Box::new(Type::U8), 2).is_fat());
+        assert_eq!(Type::Str.elem(), Some(Type::U8));
+        assert_eq!(Type::Str.decay(), Type::Ptr(Box::new(Type::U8)));
+        assert_eq!(Type::I64.elem(), None);
+    }
+
+    fn strukt(fields: &[(&str, Type)]) -> Type {
+        Type::Struct {
+            name: "S".to_string(),
+            fields: fields
+                .iter()
+                .map(|(name, typ)| (name.to_string(), typ.clone()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn struct_fields_are_aligned_like_c() {
+        let typ = strukt(&[("a", Type::U8), ("b", Type::I64), ("c", Type::U16)]);
+        assert_eq!(typ.field("a"), Some((0, Type::U8)));
+        assert_eq!(typ.field("b"), Some((8, Type::I64)));
+        assert_eq!(typ.field("c"), Some((16, Type::U16)));
+        assert_eq!(typ.field("d"), None);
+        assert_eq!((typ.size(), typ.align()), (24, 8));
+    }
+
+    #[test]
+    fn structs_are_as_aligned_as_their_widest_field() {
+        let small = strukt(&[("a", Type::U8), ("b", Type::U16), ("c", Type::U8)]);
+        assert_eq!(small.field("b"), Some((2, Type::U16)));
+        assert_eq!((small.size(), small.align()), (6, 2));
+        let nested = strukt(&[
+            ("x", Type::U8),
+            ("s", small),
+            ("n", Type::Array(Box::new(Type::I32), 3)),
+        ]);
+        assert_eq!(nested.field("s").map(|(offset, _)| offset), Some(2));
+        assert_eq!(nested.field("n").map(|(offset, _)| offset), Some(8));
+        assert_eq!((nested.size(), nested.align()), (20, 4));
+        assert_eq!(strukt(&[]).size(), 0);
+    }
+
+    #[test]
+    fn fields_are_reached_through_pointers() {
+        let typ = strukt(&[("a", Type::U8)]);
+        let ptr = Type::Ptr(Box::new(typ.clone()));
+        assert_eq!(ptr.as_struct(), Some(&typ));
+        assert_eq!(Type::Ptr(Box::new(Type::U8)).as_struct(), None);
+        assert!(typ.is_aggregate() && !ptr.is_aggregate());
+    }
+
+    #[test]
+    fn sized_ints_know_their_sign() {
+        assert!(Type::I8.is_signed() && Type::I32.is_int());
+        assert!(!Type::U32.is_signed() && Type::U64.is_int());
+        assert_eq!((Type::I16.size(), Type::U32.align()), (2, 4));
+    }
+
+    fn variant(name: &str, tag: usize, fields: &[Type]) -> Variant {
+        Variant {
+            name: name.to_string(),
+            tag,
+            fields: fields.to_vec(),
+        }
+    }
+
+    #[test]
+    fn enum_payloads_follow_the_tag() {
+        let rect = variant("Rect", 1, &[Type::I64, Type::U8]);
+        assert_eq!(rect.offsets(), vec![8, 16]);
+        let pair = variant("Pair", 2, &[Type::U8, Type::U16, Type::Str]);
+        assert_eq!(pair.offsets(), vec![8, 10, 16]);
+        let shape = Type::Enum {
+            name: "Shape".to_string(),
+            variants: vec![variant("Empty", 0, &[]), rect, pair],
+        };
+        assert_eq!((shape.size(), shape.align()), (32, 8));
+        assert!(shape.is_aggregate());
+        assert_eq!(shape.variant("Rect").map(|v| v.tag), Some(1));
+        assert_eq!(shape.variant("Circle"), None);
+    }
+
+    #[test]
+    fn enums_without_payloads_are_just_a_tag() {
+        let color = Type::Enum {
+            name: "Color".to_string(),
+            variants: vec![variant("Red", 0, &[]), variant("Blue", 5, &[])],
+        };
+        assert_eq!((color.size(), color.align()), (8, 8));
+        assert!(!color.is_aggregate());
+    }
+
+    #[test]
+    fn vecs_are_a_pointer_a_length_and_a_capacity() {
+        let string = Type::Vec(Box::new(Type::U8));
+        assert_eq!(string.size(), 24);
+        assert_eq!(string.align(), 8);
+        assert!(string.is_aggregate());
+        assert_eq!(string.elem(), Some(Type::U8));
+    }
+
+    #[test]
+    fn only_bytes_are_text() {
+        let bytes = |wrap: fn(Box<Type>) -> Type| wrap(Box::new(Type::U8));
+        assert!(Type::Str.is_text());
+        assert!(bytes(Type::Slice).is_text() && bytes(Type::Vec).is_text());
+        assert!(!bytes(Type::Ptr).is_text());
+        assert!(!Type::Slice(Box::new(Type::I64)).is_text());
+    }
+}